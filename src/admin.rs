@@ -0,0 +1,228 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+
+use anyhow::Result;
+use anyhow::bail;
+use anyhow::format_err;
+use chrono::Utc;
+use reqwest::Client;
+
+use crate::commands::Caller;
+use crate::config;
+use crate::transport::Outbox;
+use crate::transport::Reply;
+use crate::webs::Context;
+
+/// Runs an admin command, if the caller is allowed to; returns the replies.
+pub fn handle(
+    http: &Client,
+    context: &Context,
+    outbox: &dyn Outbox,
+    caller: &Caller<'_>,
+    command: &str,
+    args: &str,
+) -> Vec<String> {
+    let admin = &context.config.admin;
+    let text = format!("{} {}", command, args);
+    let text = text.trim_end();
+    if !is_operator(admin, caller.account, caller.hostmask) {
+        audit(admin, caller, text, "denied");
+        return vec!["You are not an operator.".to_string()];
+    }
+
+    match run(http, context, outbox, caller, command, args) {
+        Ok(replies) => {
+            audit(admin, caller, text, "ok");
+            replies
+        }
+        Err(e) => {
+            audit(admin, caller, text, &format!("failed: {:#}", e));
+            vec![format!("It did not work: {:#}", e)]
+        }
+    }
+}
+
+fn run(
+    http: &Client,
+    context: &Context,
+    outbox: &dyn Outbox,
+    caller: &Caller,
+    command: &str,
+    args: &str,
+) -> Result<Vec<String>> {
+    let done = |message: String| Ok(vec![message]);
+
+    match command {
+        "join" => {
+            let channel = channel(args)?;
+            outbox.join(channel)?;
+            done(format!("Joining {}.", channel))
+        }
+        "part" => {
+            let (channel, reason) = args.split_once(' ').unwrap_or((args, ""));
+            let channel = self::channel(channel)?;
+            let reason = match reason.trim() {
+                "" => format!("asked to leave by {}", caller.nick),
+                reason => reason.to_string(),
+            };
+            outbox.part(channel, &reason)?;
+            done(format!("Leaving {}.", channel))
+        }
+        "say" => {
+            let (channel, message) = args
+                .split_once(' ')
+                .ok_or_else(|| format_err!("usage: !say #chan text"))?;
+            outbox.send(Reply {
+                target: self::channel(channel)?,
+                text: message.trim(),
+                notice: false,
+                in_reply_to: None,
+            })?;
+            done(format!("Said it in {}.", channel))
+        }
+        "mute" | "unmute" => {
+            let channel = channel(args)?;
+            let muted = command == "mute";
+            context.state.set_muted(caller.network, channel, muted);
+            done(format!(
+                "{} {}.",
+                if muted { "Muted in" } else { "Unmuted in" },
+                channel
+            ))
+        }
+        "reload" => {
+            context.reload(http)?;
+            done("Reloaded rules, scripts and link cleaning; anything else needs a restart.".into())
+        }
+        "clear" => {
+            context.state.clear_caches();
+            done("Cleared cached titles, rate limits and api tokens.".to_string())
+        }
+        "status" => {
+            let status = context.state.provider_status();
+            if status.is_empty() {
+                return done("Nothing has been titled yet.".to_string());
+            }
+            Ok(status)
+        }
+        "quit" => {
+            let reason = match args {
+                "" => format!("asked to quit by {}", caller.nick),
+                reason => reason.to_string(),
+            };
+            context.state.shut_down(&reason);
+            done("Bye.".to_string())
+        }
+        _ => bail!("no such admin command: {:?}", command),
+    }
+}
+
+fn channel(arg: &str) -> Result<&str> {
+    let arg = arg.trim();
+    if arg.is_empty() || arg.contains(' ') {
+        bail!("expected a channel, not {:?}", arg);
+    }
+    Ok(arg)
+}
+
+pub fn is_operator(admin: &config::Admin, account: Option<&str>, hostmask: Option<&str>) -> bool {
+    if let Some(account) = account
+        && admin
+            .accounts
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(account))
+    {
+        return true;
+    }
+
+    hostmask.is_some_and(|hostmask| {
+        admin
+            .hostmasks
+            .iter()
+            .any(|pattern| glob(&pattern.to_lowercase(), &hostmask.to_lowercase()))
+    })
+}
+
+/// `*` matches any run of characters, `?` any single one.
+fn glob(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    // where to resume if the most recent `*` should have eaten more
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+fn audit(admin: &config::Admin, caller: &Caller, text: &str, outcome: &str) {
+    let line = format!(
+        "{} {} {} account={:?} hostmask={:?}: {:?} -> {}",
+        Utc::now().to_rfc3339(),
+        caller.network,
+        caller.nick,
+        caller.account,
+        caller.hostmask,
+        text,
+        outcome
+    );
+    warn!(target: "unsnap::audit", "{}", line);
+
+    if let Some(path) = &admin.audit_log {
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| writeln!(file, "{}", line));
+        if let Err(e) = written {
+            warn!("writing audit log {:?}: {:?}", path, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::glob;
+    use super::is_operator;
+    use crate::config::Admin;
+
+    #[test]
+    fn globbing() {
+        assert!(glob("*!*@user/faux", "faux!~faux@user/faux"));
+        assert!(glob("faux!?faux@*", "faux!~faux@example.com"));
+        assert!(glob("*", ""));
+        assert!(glob("a*b*c", "aXXbYYbc"));
+        assert!(!glob("*!*@user/faux", "faux!~faux@user/fauxx"));
+        assert!(!glob("faux!*", "notfaux!x@y"));
+    }
+
+    #[test]
+    fn operators() {
+        let admin = Admin {
+            accounts: vec!["Faux".to_string()],
+            hostmasks: vec!["*!*@user/faux".to_string()],
+            audit_log: None,
+        };
+        assert!(is_operator(&admin, Some("faux"), None));
+        assert!(is_operator(&admin, None, Some("f!f@User/Faux")));
+        assert!(!is_operator(&admin, Some("other"), Some("o!o@example.com")));
+        assert!(!is_operator(&admin, None, None));
+        assert!(!is_operator(&Admin::default(), Some("faux"), None));
+    }
+}
@@ -0,0 +1,65 @@
+use std::time::Duration;
+
+/// Exponential backoff between reconnection attempts, with full jitter on the upper half.
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    attempt: u32,
+}
+
+impl Backoff {
+    pub fn new(initial: Duration, max: Duration) -> Backoff {
+        Backoff {
+            initial,
+            max,
+            attempt: 0,
+        }
+    }
+
+    /// The ceiling for the next delay, before jitter.
+    fn ceiling(&self) -> Duration {
+        self.initial
+            .checked_mul(1 << self.attempt.min(16))
+            .unwrap_or(self.max)
+            .min(self.max)
+    }
+
+    pub fn next_delay(&mut self) -> Duration {
+        let ceiling = self.ceiling();
+        self.attempt = self.attempt.saturating_add(1);
+        // somewhere between half and all of the ceiling, so a netsplit doesn't cause a herd
+        ceiling / 2 + ceiling.mul_f64(fastrand::f64() / 2.)
+    }
+
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Backoff;
+
+    #[test]
+    fn grows_and_caps() {
+        let mut backoff = Backoff::new(Duration::from_secs(2), Duration::from_secs(60));
+        let mut ceilings = Vec::new();
+        for _ in 0..8 {
+            let ceiling = backoff.ceiling();
+            let delay = backoff.next_delay();
+            assert!(delay >= ceiling / 2 && delay <= ceiling, "{:?}", delay);
+            ceilings.push(ceiling.as_secs());
+        }
+        assert_eq!(vec![2, 4, 8, 16, 32, 60, 60, 60], ceilings);
+
+        backoff.reset();
+        assert_eq!(Duration::from_secs(2), backoff.ceiling());
+
+        for _ in 0..100 {
+            backoff.next_delay();
+        }
+        assert_eq!(Duration::from_secs(60), backoff.ceiling());
+    }
+}
@@ -0,0 +1,534 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context as _;
+use anyhow::Result;
+use anyhow::format_err;
+use chrono::Utc;
+use reqwest::Client;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+
+use crate::commands;
+use crate::config;
+use crate::config::Delivery;
+use crate::db;
+use crate::split;
+use crate::titles;
+use crate::transport::Doing;
+use crate::transport::Event;
+use crate::transport::Message;
+use crate::transport::Outbox;
+use crate::transport::Reply;
+use crate::transport::Transport;
+use crate::webs::Context;
+
+/// Processes everything the transport produces, until it runs out.
+pub async fn run<T: Transport>(
+    http: Client,
+    context: Arc<Context>,
+    mut transport: T,
+) -> Result<()> {
+    let mut tasks = JoinSet::new();
+    let mut shutdown = context.state.shutdown();
+    let mut quitting = false;
+    let reminders = tokio::spawn(send_reminders(
+        Arc::clone(&context),
+        transport.outbox(),
+        transport.name().to_string(),
+    ));
+    // the database is written to in order, and away from the event loop, so a slow disk
+    // doesn't hold up everything else
+    let (remembering, noticed) = mpsc::unbounded_channel();
+    let rememberer = tokio::task::spawn_blocking({
+        let context = Arc::clone(&context);
+        let outbox = transport.outbox();
+        let network = transport.name().to_string();
+        move || remember(&context, outbox.as_ref(), &network, noticed)
+    });
+
+    loop {
+        let event = if quitting {
+            // only waiting for the goodbye to be delivered
+            match tokio::time::timeout(QUIT_GRACE, transport.next()).await {
+                Ok(Some(_)) => continue,
+                Ok(None) | Err(_) => break,
+            }
+        } else {
+            tokio::select! {
+                event = transport.next() => event,
+                _ = shutdown.changed() => {
+                    let reason = shutdown.borrow().clone().unwrap_or_default();
+                    info!("{}: quitting: {:?}", transport.name(), reason);
+                    if let Err(e) = transport.outbox().quit(&reason) {
+                        warn!("{}: quitting: {:?}", transport.name(), e);
+                    }
+                    quitting = true;
+                    continue;
+                }
+            }
+        };
+
+        let event = match event {
+            Some(event) => event,
+            None => break,
+        };
+
+        // forget about anything which has already finished
+        while tasks.try_join_next().is_some() {}
+
+        let _ = remembering.send(Noticed::new(&transport, &event));
+
+        match event {
+            Event::Message(message) if is_replay(&message) => {
+                info!("{}: ignoring old message: {:?}", transport.name(), message);
+            }
+            Event::Message(message) => {
+                let incoming = Incoming::new(&transport, message);
+                tasks.spawn(process_msg_or_log(
+                    http.clone(),
+                    transport.outbox(),
+                    Arc::clone(&context),
+                    incoming,
+                ));
+            }
+            Event::Activity(_) => (),
+        }
+    }
+
+    info!("{}: finished", transport.name());
+    reminders.abort();
+    drop(remembering);
+    if let Err(e) = rememberer.await {
+        warn!("{}: remembering: {:?}", transport.name(), e);
+    }
+
+    // let any replies in flight get sent
+    while tasks.join_next().await.is_some() {}
+
+    Ok(())
+}
+
+/// An event, with what the transport said about it, for `remember`.
+struct Noticed {
+    event: Event,
+    /// it's somewhere `!seen` may remember
+    seen: bool,
+    /// they've turned up, so any `!tell`s waiting for them can be passed on
+    arrived: bool,
+    /// the channel they're in, if any
+    channel: Option<(String, config::ChannelSettings)>,
+}
+
+impl Noticed {
+    fn new<T: Transport>(transport: &T, event: &Event) -> Noticed {
+        let in_channel = |channel: &str| {
+            let settings = transport.channel_settings(channel);
+            (settings.seen, Some((channel.to_string(), settings)))
+        };
+        let (seen, arrived, channel) = match event {
+            Event::Message(message) if message.private => (false, !is_replay(message), None),
+            Event::Message(message) => {
+                let (seen, channel) = in_channel(&message.target);
+                (seen, !is_replay(message), channel)
+            }
+            Event::Activity(activity) => match &activity.doing {
+                Doing::Joined { channel } => {
+                    let (seen, channel) = in_channel(channel);
+                    (seen, true, channel)
+                }
+                Doing::Parted { channel, .. } | Doing::Kicked { channel, .. } => {
+                    (in_channel(channel).0, false, None)
+                }
+                // news everywhere, unless everywhere we'd have seen it asked us not to
+                Doing::Quit { channels, .. } | Doing::Renamed { channels, .. } => {
+                    let seen = channels
+                        .iter()
+                        .any(|channel| transport.channel_settings(channel).seen);
+                    (seen, false, None)
+                }
+            },
+        };
+        Noticed {
+            event: event.clone(),
+            seen,
+            arrived,
+            channel,
+        }
+    }
+}
+
+/// Notes who's around, for `!seen` and `!tell`, and passes on messages to people who've turned
+/// up; blocks, until there's nothing more to notice.
+fn remember(
+    context: &Context,
+    outbox: &dyn Outbox,
+    network: &str,
+    mut noticed: mpsc::UnboundedReceiver<Noticed>,
+) {
+    while let Some(Noticed {
+        event,
+        seen,
+        arrived,
+        channel,
+    }) = noticed.blocking_recv()
+    {
+        let (nick, account) = match &event {
+            Event::Message(message) => {
+                if seen && let Err(e) = context.seen.message(network, message) {
+                    warn!("{}: remembering {:?}: {:?}", network, message.source, e);
+                }
+                (&message.source, message.account.as_deref())
+            }
+            Event::Activity(activity) => {
+                if seen && let Err(e) = context.seen.activity(network, activity) {
+                    warn!("{}: remembering {:?}: {:?}", network, activity.nick, e);
+                }
+                (&activity.nick, activity.account.as_deref())
+            }
+        };
+
+        if arrived
+            && let Err(e) = deliver_tells(
+                context,
+                outbox,
+                network,
+                nick,
+                account,
+                channel
+                    .as_ref()
+                    .map(|(channel, settings)| (channel.as_str(), settings)),
+            )
+        {
+            warn!("{}: passing on to {:?}: {:?}", network, nick, e);
+        }
+
+        if let Err(e) = context.tells.noticed(network, nick, account) {
+            warn!("{}: remembering {:?}'s account: {:?}", network, nick, e);
+        }
+    }
+}
+
+/// Passes on any `!tell` messages waiting for someone; in the channel they're in, if that's
+/// allowed, otherwise privately.
+fn deliver_tells(
+    context: &Context,
+    outbox: &dyn Outbox,
+    network: &str,
+    nick: &str,
+    account: Option<&str>,
+    channel: Option<(&str, &config::ChannelSettings)>,
+) -> Result<()> {
+    let now = Utc::now();
+    let waiting = context.tells.claim(network, nick, account, now)?;
+    if waiting.is_empty() {
+        return Ok(());
+    }
+
+    let (target, prefix, notice) = match channel {
+        Some((channel, settings))
+            if context.config.tell.deliver == Delivery::Channel
+                && settings.commands
+                && !context.state.is_muted(network, channel) =>
+        {
+            (channel, format!("{}: ", nick), settings.notice)
+        }
+        _ => (nick, String::new(), false),
+    };
+
+    let room = outbox.max_payload(target).saturating_sub(prefix.len());
+    for (i, tell) in waiting.iter().enumerate() {
+        let sent = split::split(&tell.line(now), room, context.config.output.max_lines)
+            .iter()
+            .try_for_each(|line| {
+                outbox.send(Reply {
+                    target,
+                    text: &format!("{}{}", prefix, line),
+                    notice,
+                    in_reply_to: None,
+                })
+            });
+        if let Err(e) = sent {
+            // keep the rest for when they're next around
+            for tell in &waiting[i..] {
+                context.tells.release(tell)?;
+            }
+            return Err(e);
+        }
+        context.tells.done(tell)?;
+    }
+    Ok(())
+}
+
+/// Sends `!remind` reminders for the network as they come due, including any which came due while
+/// we weren't running; runs until aborted.
+async fn send_reminders(context: Arc<Context>, outbox: Arc<dyn Outbox>, network: String) {
+    // give the connection a chance to come up, and join channels
+    tokio::time::sleep(REMINDER_SETTLE).await;
+
+    loop {
+        // listening before looking, so a reminder set in between still wakes us
+        let changed = context.reminders.changed();
+        tokio::pin!(changed);
+        changed.as_mut().enable();
+
+        let next = db::blocking({
+            let (context, outbox, network) =
+                (Arc::clone(&context), Arc::clone(&outbox), network.clone());
+            move || {
+                send_due(&context, outbox.as_ref(), &network).context("sending")?;
+                context.reminders.next(&network).context("finding")
+            }
+        });
+        let wait = match next.await {
+            Ok(next) => next
+                .map(|due| (due - Utc::now()).to_std().unwrap_or_default())
+                .unwrap_or(REMINDER_IDLE)
+                .min(REMINDER_IDLE),
+            Err(e) => {
+                warn!("{}: reminders: {:?}", network, e);
+                REMINDER_RETRY
+            }
+        };
+
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => (),
+            _ = changed => (),
+        }
+    }
+}
+
+/// Sends every due reminder; in private, to the owner, if the bot is muted in the channel.
+fn send_due(context: &Context, outbox: &dyn Outbox, network: &str) -> Result<()> {
+    for reminder in context.reminders.due(network, Utc::now())? {
+        let target = match context.state.is_muted(network, &reminder.target) {
+            true => &reminder.owner,
+            false => &reminder.target,
+        };
+        let room = outbox.max_payload(target);
+        for line in split::split(&reminder.line(), room, context.config.output.max_lines) {
+            outbox.send(Reply {
+                target,
+                text: &line,
+                notice: false,
+                in_reply_to: None,
+            })?;
+        }
+        context.reminders.done(&reminder)?;
+    }
+    Ok(())
+}
+
+/// How long after starting before reminders are sent.
+const REMINDER_SETTLE: Duration = Duration::from_secs(10);
+
+/// The longest between checks for reminders, in case the clock jumps.
+const REMINDER_IDLE: Duration = Duration::from_secs(60 * 60);
+
+/// How long to wait before trying again, when sending a reminder failed, e.g. when disconnected.
+const REMINDER_RETRY: Duration = Duration::from_secs(60);
+
+/// How long to wait for a network to acknowledge our leaving.
+const QUIT_GRACE: Duration = Duration::from_secs(5);
+
+/// Messages the network says are older than this are history being played back (e.g. by a
+/// bouncer), not conversation.
+const MAX_AGE: Duration = Duration::from_secs(5 * 60);
+
+fn is_replay(message: &Message) -> bool {
+    message
+        .time
+        .and_then(|time| (Utc::now() - time).to_std().ok())
+        .is_some_and(|age| age > MAX_AGE)
+}
+
+/// A message to us, or to a channel we're in, reduced to what processing needs.
+#[derive(Clone, Debug)]
+struct Incoming {
+    /// the transport's name for itself
+    network: String,
+    /// what we're called there, for commands addressed to us
+    own_nick: String,
+    nick: String,
+    /// the sender's services account, where the network tells us
+    account: Option<String>,
+    hostmask: Option<String>,
+    /// the network's id for the message, so replies can be threaded to it
+    id: Option<String>,
+    /// where replies go: the channel, or the sender of a private message
+    reply_to: String,
+    text: String,
+    /// sent directly to us, rather than to a channel
+    private: bool,
+    /// an emote (`/me`), which is titled, but never treated as a command
+    action: bool,
+    /// for the channel; the defaults for private messages
+    settings: config::ChannelSettings,
+}
+
+impl Incoming {
+    fn new<T: Transport>(transport: &T, message: Message) -> Incoming {
+        let settings = if message.private {
+            config::ChannelSettings::default()
+        } else {
+            transport.channel_settings(&message.target)
+        };
+
+        Incoming {
+            reply_to: if message.private {
+                message.source.to_string()
+            } else {
+                message.target
+            },
+            network: transport.name().to_string(),
+            own_nick: transport.nick(),
+            nick: message.source,
+            account: message.account,
+            hostmask: message.hostmask,
+            id: message.id,
+            text: message.text,
+            private: message.private,
+            action: message.action,
+            settings,
+        }
+    }
+
+    fn titles_allowed(&self, context: &Context) -> bool {
+        self.settings.titles && (!self.private || context.config.query.titles)
+    }
+}
+
+async fn process_msg_or_log(
+    http: Client,
+    outbox: Arc<dyn Outbox>,
+    context: Arc<Context>,
+    incoming: Incoming,
+) -> () {
+    if let Err(e) = process_msg(http, context, &incoming, outbox.as_ref())
+        .await
+        .with_context(|| {
+            format_err!(
+                "processing < {:?} ({:?})> {:?}",
+                incoming.nick,
+                incoming.account,
+                incoming.text
+            )
+        })
+    {
+        warn!("process_msg failed: {:?}", e)
+    }
+}
+
+/// Sends a line back to wherever the message came from, in the channel's style.
+fn reply(outbox: &dyn Outbox, incoming: &Incoming, text: &str) -> Result<()> {
+    outbox
+        .send(Reply {
+            target: &incoming.reply_to,
+            text,
+            notice: incoming.settings.notice,
+            in_reply_to: incoming.id.as_deref(),
+        })
+        .with_context(|| format_err!("replying to {:?}", incoming.reply_to))
+}
+
+async fn process_msg(
+    http: Client,
+    context: Arc<Context>,
+    incoming: &Incoming,
+    outbox: &dyn Outbox,
+) -> Result<()> {
+    let nick = &incoming.nick;
+    let msg = &incoming.text;
+    let limit = outbox.max_payload(&incoming.reply_to);
+
+    let command = commands::parse(&context.config.commands, &incoming.own_nick, msg)
+        .filter(|_| !incoming.action)
+        .and_then(|(name, args)| Some((context.commands.find(name)?, args)))
+        .filter(|(command, _)| {
+            commands::available(&context, command, &incoming.settings, incoming.private)
+        });
+
+    // operators can still get a muted bot's attention
+    let muted = !incoming.private
+        && context
+            .state
+            .is_muted(&incoming.network, &incoming.reply_to);
+    if muted && !command.is_some_and(|(command, _)| command.is_admin()) {
+        return Ok(());
+    }
+
+    if let Some((command, args)) = command {
+        if !context
+            .commands
+            .cooled_down(&incoming.network, nick, command)
+        {
+            info!(
+                "{}: {} is using {} too often",
+                incoming.network, nick, command.name
+            );
+            return Ok(());
+        }
+
+        let invocation = commands::Invocation {
+            caller: commands::Caller {
+                network: &incoming.network,
+                nick,
+                account: incoming.account.as_deref(),
+                hostmask: incoming.hostmask.as_deref(),
+            },
+            command,
+            args,
+            settings: &incoming.settings,
+            private: incoming.private,
+            reply_to: &incoming.reply_to,
+        };
+
+        let prefix = if command.is_admin() || incoming.private {
+            String::new()
+        } else {
+            format!("{}: ", nick)
+        };
+        let room = limit.saturating_sub(prefix.len());
+        for line in commands::run(&http, &context, outbox, &invocation).await? {
+            for line in split::split(&line, room, context.config.output.max_lines) {
+                reply(outbox, incoming, &format!("{}{}", prefix, line))?;
+            }
+        }
+        return Ok(());
+    }
+
+    if !incoming.titles_allowed(&context) {
+        return Ok(());
+    }
+
+    let settings = &incoming.settings;
+    let address = if settings.address && !incoming.private {
+        format!("{}: ", nick)
+    } else {
+        String::new()
+    };
+
+    for title in titles::titles_for(http, Arc::clone(&context), msg).await? {
+        assert!(!title.title.contains(|c: char| c.is_control()));
+        if !incoming.private && settings.urls {
+            let context = Arc::clone(&context);
+            let (network, channel, nick, kept) = (
+                incoming.network.clone(),
+                incoming.reply_to.clone(),
+                nick.clone(),
+                title.clone(),
+            );
+            let recorded = db::blocking(move || {
+                context
+                    .urls
+                    .record(&network, &channel, &nick, &kept, Utc::now())
+            });
+            if let Err(e) = recorded.await {
+                warn!("{}: keeping {:?}: {:?}", incoming.network, title.url, e);
+            }
+        }
+        let title = title.render(limit.saturating_sub(address.len()), settings.formatting);
+        reply(outbox, incoming, &format!("{}{}", address, title))?;
+    }
+    Ok(())
+}
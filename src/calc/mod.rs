@@ -0,0 +1,493 @@
+//! A small calculator, for when qalc isn't available: arithmetic, common functions, and
+//! conversions between units of length, mass, temperature and data.
+
+mod units;
+
+use std::f64::consts;
+
+use anyhow::Result;
+use anyhow::bail;
+use anyhow::format_err;
+
+use self::units::Unit;
+
+/// Answers each `;;`-separated expression, as qalc would, or says what's wrong with it.
+pub fn answer(input: &str) -> String {
+    input
+        .split(" ;; ")
+        .map(str::trim)
+        .map(|expression| match evaluate(expression) {
+            Ok(value) => format!("{} = {}", expression, value),
+            Err(e) => format!("{}: {}", expression, e),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The value of an expression, which may end with a conversion: `5 feet to cm`.
+pub fn evaluate(expression: &str) -> Result<String> {
+    // the last, so `3 in in cm` is three inches
+    let conversion = [" to ", " in ", " -> "]
+        .iter()
+        .filter_map(|keyword| {
+            let (value, unit) = expression.rsplit_once(keyword)?;
+            Some((value, units::lookup(unit.trim())?))
+        })
+        .max_by_key(|(value, _)| value.len());
+
+    let (value, target) = match conversion {
+        Some((value, unit)) => (parse(value)?, Some(unit)),
+        None => (parse(expression)?, None),
+    };
+
+    Ok(match (value.unit, target) {
+        (Some(unit), Some(target)) => {
+            let converted = units::convert(value.number, &unit, &target)?;
+            format!("{} {}", number(converted), target.symbol)
+        }
+        (None, Some(target)) => bail!("there's nothing to convert to {}", target.symbol),
+        (Some(unit), None) => format!("{} {}", number(value.number), unit.symbol),
+        (None, None) => number(value.number),
+    })
+}
+
+/// A number, perhaps of some unit.
+#[derive(Clone, Debug)]
+struct Quantity {
+    number: f64,
+    unit: Option<Unit>,
+}
+
+impl Quantity {
+    fn plain(number: f64) -> Quantity {
+        Quantity { number, unit: None }
+    }
+
+    /// This plain number, or an error naming the operation.
+    fn expect_plain(&self, what: &str) -> Result<f64> {
+        match &self.unit {
+            None => Ok(self.number),
+            Some(unit) => bail!("{} needs a plain number, not {}", what, unit.symbol),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f64),
+    Name(String),
+    Op(char),
+    Open,
+    Close,
+    Comma,
+}
+
+fn tokenise(text: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '0'..='9' | '.' => {
+                let mut literal = String::new();
+                while let Some(&c) = chars.peek() {
+                    let exponent = (c == 'e' || c == 'E') && !literal.contains(['e', 'E']);
+                    if c.is_ascii_digit() || c == '.' {
+                        literal.push(c);
+                    } else if exponent {
+                        // only an exponent if digits follow, otherwise it's `2e`, two times e
+                        let mut ahead = chars.clone();
+                        ahead.next();
+                        let sign = ahead.next_if(|&c| c == '-' || c == '+');
+                        if !ahead.peek().is_some_and(char::is_ascii_digit) {
+                            break;
+                        }
+                        literal.push(c);
+                        chars.next();
+                        if let Some(sign) = sign {
+                            literal.push(sign);
+                            chars.next();
+                        }
+                        continue;
+                    } else {
+                        break;
+                    }
+                    chars.next();
+                }
+                let number = literal
+                    .parse()
+                    .map_err(|_| format_err!("not a number: {}", literal))?;
+                tokens.push(Token::Number(number));
+            }
+            c if c.is_alphabetic() || c == '°' || c == '_' => {
+                let mut name = String::new();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_alphanumeric() || c == '°' || c == '_') {
+                        break;
+                    }
+                    name.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Name(name));
+            }
+            _ => {
+                chars.next();
+                tokens.push(match c {
+                    '(' => Token::Open,
+                    ')' => Token::Close,
+                    ',' => Token::Comma,
+                    '*' if chars.next_if_eq(&'*').is_some() => Token::Op('^'),
+                    '+' | '-' | '*' | '/' | '^' | '%' | '!' => Token::Op(c),
+                    '−' => Token::Op('-'),
+                    '×' | '·' => Token::Op('*'),
+                    '÷' => Token::Op('/'),
+                    _ => bail!("unexpected {:?}", c),
+                });
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn parse(text: &str) -> Result<Quantity> {
+    let mut parser = Parser {
+        tokens: tokenise(text)?,
+        next: 0,
+        depth: 0,
+    };
+    if parser.tokens.is_empty() {
+        bail!("nothing to calculate");
+    }
+    let value = parser.sum()?;
+    match parser.peek() {
+        None => Ok(value),
+        Some(token) => bail!("unexpected {}", describe(token)),
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Number(number) => self::number(*number),
+        Token::Name(name) => name.to_string(),
+        Token::Op(c) => c.to_string(),
+        Token::Open => "(".to_string(),
+        Token::Close => ")".to_string(),
+        Token::Comma => ",".to_string(),
+    }
+}
+
+/// How far brackets, signs and powers may nest, well before the stack runs out.
+const MAX_DEPTH: usize = 64;
+
+/// Recursive descent, loosest binding first.
+struct Parser {
+    tokens: Vec<Token>,
+    next: usize,
+    /// every level of nesting passes through `unary`, which counts them
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next)
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        let matched = self.peek() == Some(token);
+        if matched {
+            self.next += 1;
+        }
+        matched
+    }
+
+    fn sum(&mut self) -> Result<Quantity> {
+        let mut value = self.product()?;
+        loop {
+            if self.eat(&Token::Op('+')) {
+                value = add(value, self.product()?, 1.)?;
+            } else if self.eat(&Token::Op('-')) {
+                value = add(value, self.product()?, -1.)?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn product(&mut self) -> Result<Quantity> {
+        let mut value = self.unary()?;
+        loop {
+            if self.eat(&Token::Op('*')) {
+                value = multiply(value, self.unary()?)?;
+            } else if self.eat(&Token::Op('/')) {
+                value = divide(value, self.unary()?)?;
+            } else if self.eat(&Token::Op('%')) {
+                let divisor = self.unary()?.expect_plain("%")?;
+                value = Quantity::plain(value.expect_plain("%")? % divisor);
+            } else if matches!(
+                self.peek(),
+                Some(Token::Number(_) | Token::Name(_) | Token::Open)
+            ) {
+                // `5 feet`, `2 pi`, `3(4 + 5)`
+                value = multiply(value, self.unary()?)?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn unary(&mut self) -> Result<Quantity> {
+        if self.depth >= MAX_DEPTH {
+            bail!("too deeply nested");
+        }
+        self.depth += 1;
+        let value = self.signed();
+        self.depth -= 1;
+        value
+    }
+
+    fn signed(&mut self) -> Result<Quantity> {
+        if self.eat(&Token::Op('-')) {
+            let mut value = self.unary()?;
+            value.number = -value.number;
+            return Ok(value);
+        }
+        if self.eat(&Token::Op('+')) {
+            return self.unary();
+        }
+        self.power()
+    }
+
+    fn power(&mut self) -> Result<Quantity> {
+        let base = self.factorial()?;
+        if !self.eat(&Token::Op('^')) {
+            return Ok(base);
+        }
+        // right associative, and `2^-1` is a half
+        let exponent = self.unary()?.expect_plain("^")?;
+        Ok(Quantity::plain(base.expect_plain("^")?.powf(exponent)))
+    }
+
+    fn factorial(&mut self) -> Result<Quantity> {
+        let value = self.atom()?;
+        if !self.eat(&Token::Op('!')) {
+            return Ok(value);
+        }
+        let n = value.expect_plain("!")?;
+        if n < 0. || n.fract() != 0. || n > 170. {
+            bail!("! needs a whole number from 0 to 170");
+        }
+        Ok(Quantity::plain((1..=n as u64).map(|i| i as f64).product()))
+    }
+
+    fn atom(&mut self) -> Result<Quantity> {
+        let token = self
+            .tokens
+            .get(self.next)
+            .cloned()
+            .ok_or_else(|| format_err!("unexpected end"))?;
+        self.next += 1;
+
+        match token {
+            Token::Number(number) => Ok(Quantity::plain(number)),
+            Token::Open => {
+                let value = self.sum()?;
+                if !self.eat(&Token::Close) {
+                    bail!("missing )");
+                }
+                Ok(value)
+            }
+            Token::Name(name) if self.eat(&Token::Open) => {
+                let mut args = vec![self.sum()?.expect_plain(&name)?];
+                while self.eat(&Token::Comma) {
+                    args.push(self.sum()?.expect_plain(&name)?);
+                }
+                if !self.eat(&Token::Close) {
+                    bail!("missing )");
+                }
+                Ok(Quantity::plain(function(&name, &args)?))
+            }
+            Token::Name(name) => {
+                if let Some(constant) = constant(&name) {
+                    return Ok(Quantity::plain(constant));
+                }
+                let unit = units::lookup(&name)
+                    .ok_or_else(|| format_err!("unknown unit or constant: {}", name))?;
+                Ok(Quantity {
+                    number: 1.,
+                    unit: Some(unit),
+                })
+            }
+            token => bail!("unexpected {}", describe(&token)),
+        }
+    }
+}
+
+/// `left + sign * right`, in the left's unit.
+fn add(left: Quantity, right: Quantity, sign: f64) -> Result<Quantity> {
+    let right = match (&left.unit, &right.unit) {
+        (None, None) => right.number,
+        (Some(unit), Some(other)) => units::convert(right.number, other, unit)?,
+        (Some(unit), None) | (None, Some(unit)) => {
+            bail!("can't add a plain number to {}", unit.symbol)
+        }
+    };
+    Ok(Quantity {
+        number: left.number + sign * right,
+        unit: left.unit,
+    })
+}
+
+fn multiply(left: Quantity, right: Quantity) -> Result<Quantity> {
+    let unit = match (left.unit, right.unit) {
+        (Some(unit), Some(other)) => {
+            bail!("can't multiply {} by {}", unit.symbol, other.symbol)
+        }
+        (unit, None) | (None, unit) => unit,
+    };
+    Ok(Quantity {
+        number: left.number * right.number,
+        unit,
+    })
+}
+
+fn divide(left: Quantity, right: Quantity) -> Result<Quantity> {
+    match (left.unit, right.unit) {
+        // a ratio: `1 mile / 1 km`
+        (Some(unit), Some(other)) => Ok(Quantity::plain(
+            left.number / units::convert(right.number, &other, &unit)?,
+        )),
+        (unit, None) => Ok(Quantity {
+            number: left.number / right.number,
+            unit,
+        }),
+        (None, Some(unit)) => bail!("can't divide by {}", unit.symbol),
+    }
+}
+
+fn constant(name: &str) -> Option<f64> {
+    Some(match name {
+        "pi" | "π" => consts::PI,
+        "tau" | "τ" => consts::TAU,
+        "e" => consts::E,
+        _ => return None,
+    })
+}
+
+fn function(name: &str, args: &[f64]) -> Result<f64> {
+    let one = |f: fn(f64) -> f64| match args {
+        &[x] => Ok(f(x)),
+        _ => Err(format_err!("{} takes one number", name)),
+    };
+    let two = |f: fn(f64, f64) -> f64| match args {
+        &[x, y] => Ok(f(x, y)),
+        _ => Err(format_err!("{} takes two numbers", name)),
+    };
+
+    match name {
+        "sqrt" => one(f64::sqrt),
+        "cbrt" => one(f64::cbrt),
+        "abs" => one(f64::abs),
+        "exp" => one(f64::exp),
+        "ln" => one(f64::ln),
+        "log" | "log10" => one(f64::log10),
+        "log2" => one(f64::log2),
+        "sin" => one(f64::sin),
+        "cos" => one(f64::cos),
+        "tan" => one(f64::tan),
+        "asin" => one(f64::asin),
+        "acos" => one(f64::acos),
+        "atan" => one(f64::atan),
+        "sinh" => one(f64::sinh),
+        "cosh" => one(f64::cosh),
+        "tanh" => one(f64::tanh),
+        "floor" => one(f64::floor),
+        "ceil" => one(f64::ceil),
+        "round" => one(f64::round),
+        "min" => two(f64::min),
+        "max" => two(f64::max),
+        "atan2" => two(f64::atan2),
+        "hypot" => two(f64::hypot),
+        _ => bail!("unknown function: {}", name),
+    }
+}
+
+/// Twelve significant figures, without trailing zeros; an exponent if it's huge or tiny.
+fn number(x: f64) -> String {
+    if x.is_nan() {
+        return "undefined".to_string();
+    }
+    if x.is_infinite() {
+        return if x > 0. { "infinity" } else { "-infinity" }.to_string();
+    }
+    if x == 0. {
+        return "0".to_string();
+    }
+
+    let magnitude = x.abs().log10().floor() as i32;
+    if !(-6..15).contains(&magnitude) {
+        let formatted = format!("{:.11e}", x);
+        let (mantissa, exponent) = formatted.split_once('e').expect("exponent");
+        return format!("{}e{}", trim_zeros(mantissa), exponent);
+    }
+
+    let decimals = (11 - magnitude).max(0) as usize;
+    trim_zeros(&format!("{:.*}", decimals, x)).to_string()
+}
+
+fn trim_zeros(number: &str) -> &str {
+    if number.contains('.') {
+        number.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        number
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::answer;
+    use super::evaluate;
+
+    #[test]
+    fn calculating() {
+        let eval = |expression| evaluate(expression).unwrap();
+        assert_eq!("0.3", eval("0.1 + 0.2"));
+        assert_eq!("-4", eval("-2^2"));
+        assert_eq!("0.5", eval("2^-1"));
+        assert_eq!("14", eval("2 + 3 * 4"));
+        assert_eq!("6.28318530718", eval("2pi"));
+        assert_eq!("3", eval("sqrt(9)"));
+        assert_eq!("120", eval("5!"));
+        assert_eq!("1.5e20", eval("1.5e20"));
+        assert_eq!("152.4 cm", eval("5 feet to cm"));
+        assert_eq!("5.25 ft", eval("5 ft + 3 in"));
+        assert_eq!("7.62 cm", eval("3 in in cm"));
+        assert_eq!("-40 °F", eval("-40 C to F"));
+        assert_eq!("1073.741824 MB", eval("1 GiB -> MB"));
+        assert_eq!("0.90718474 kg", eval("4 lb / 2 to kg"));
+
+        assert!(evaluate("2 kg + 3 m").is_err());
+        assert!(evaluate("5 to cm").is_err());
+        assert!(evaluate("(1 + 2").is_err());
+        assert!(evaluate("frobnicate(2)").is_err());
+        for deep in [
+            "(".repeat(5000),
+            "-".repeat(5000) + "1",
+            "2^".repeat(5000) + "1",
+        ] {
+            assert_eq!(
+                "too deeply nested",
+                evaluate(&deep).unwrap_err().to_string()
+            );
+        }
+        assert_eq!("1", eval(&format!("{}1{}", "(".repeat(40), ")".repeat(40))));
+
+        assert_eq!(
+            "1 + 1 = 2\nfoo: unknown unit or constant: foo",
+            answer("1 + 1 ;; foo")
+        );
+    }
+}
@@ -0,0 +1,209 @@
+use anyhow::Result;
+use anyhow::bail;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Dimension {
+    Length,
+    Mass,
+    Temperature,
+    Data,
+}
+
+/// A unit, as a scale (and, for temperatures, offset) from the dimension's base unit: metres,
+/// kilograms, kelvin or bytes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Unit {
+    pub symbol: String,
+    pub dimension: Dimension,
+    scale: f64,
+    offset: f64,
+}
+
+struct Known {
+    /// the first is the symbol, shown in answers
+    names: &'static [&'static str],
+    dimension: Dimension,
+    scale: f64,
+    offset: f64,
+    /// only metric units take prefixes, e.g. km, or kilometre for a long name
+    prefixed: bool,
+    long: &'static [&'static str],
+}
+
+const fn known(names: &'static [&'static str], dimension: Dimension, scale: f64) -> Known {
+    Known {
+        names,
+        dimension,
+        scale,
+        offset: 0.,
+        prefixed: false,
+        long: &[],
+    }
+}
+
+const fn metric(
+    names: &'static [&'static str],
+    long: &'static [&'static str],
+    dimension: Dimension,
+    scale: f64,
+) -> Known {
+    Known {
+        prefixed: true,
+        long,
+        ..known(names, dimension, scale)
+    }
+}
+
+const KNOWN: &[Known] = &[
+    metric(
+        &["m"],
+        &["metre", "metres", "meter", "meters"],
+        Dimension::Length,
+        1.,
+    ),
+    known(&["in", "inch", "inches"], Dimension::Length, 0.0254),
+    known(&["ft", "foot", "feet"], Dimension::Length, 0.3048),
+    known(&["yd", "yard", "yards"], Dimension::Length, 0.9144),
+    known(&["mi", "mile", "miles"], Dimension::Length, 1609.344),
+    known(&["nmi"], Dimension::Length, 1852.),
+    metric(
+        &["g"],
+        &["gram", "grams", "gramme", "grammes"],
+        Dimension::Mass,
+        0.001,
+    ),
+    known(&["t", "tonne", "tonnes"], Dimension::Mass, 1000.),
+    known(
+        &["oz", "ounce", "ounces"],
+        Dimension::Mass,
+        0.028_349_523_125,
+    ),
+    known(
+        &["lb", "lbs", "pound", "pounds"],
+        Dimension::Mass,
+        0.453_592_37,
+    ),
+    known(&["st", "stone", "stones"], Dimension::Mass, 6.350_293_18),
+    known(&["K", "kelvin"], Dimension::Temperature, 1.),
+    Known {
+        offset: 273.15,
+        ..known(&["°C", "C", "celsius", "degC"], Dimension::Temperature, 1.)
+    },
+    Known {
+        offset: 273.15 - 32. * 5. / 9.,
+        ..known(
+            &["°F", "F", "fahrenheit", "degF"],
+            Dimension::Temperature,
+            5. / 9.,
+        )
+    },
+    metric(&["B"], &["byte", "bytes"], Dimension::Data, 1.),
+    metric(&["bit"], &["bit", "bits"], Dimension::Data, 0.125),
+];
+
+/// symbol, long name, multiplier
+const PREFIXES: &[(&str, &str, f64)] = &[
+    ("T", "tera", 1e12),
+    ("G", "giga", 1e9),
+    ("M", "mega", 1e6),
+    ("k", "kilo", 1e3),
+    ("h", "hecto", 1e2),
+    ("d", "deci", 1e-1),
+    ("c", "centi", 1e-2),
+    ("m", "milli", 1e-3),
+    ("µ", "micro", 1e-6),
+    ("u", "micro", 1e-6),
+    ("n", "nano", 1e-9),
+    ("p", "pico", 1e-12),
+];
+
+/// Only for data.
+const BINARY_PREFIXES: &[(&str, &str, f64)] = &[
+    ("Ki", "kibi", 1024.),
+    ("Mi", "mebi", 1024. * 1024.),
+    ("Gi", "gibi", 1024. * 1024. * 1024.),
+    ("Ti", "tebi", 1024. * 1024. * 1024. * 1024.),
+];
+
+/// A unit by name, with an SI (or, for data, binary) prefix if it has one.
+pub fn lookup(name: &str) -> Option<Unit> {
+    if let Some(known) = KNOWN.iter().find(|known| known.names.contains(&name)) {
+        return Some(unit(known, "", 1.));
+    }
+
+    for known in KNOWN.iter().filter(|known| known.prefixed) {
+        let prefixes = PREFIXES.iter().chain(
+            BINARY_PREFIXES
+                .iter()
+                .filter(|_| known.dimension == Dimension::Data),
+        );
+        for &(symbol, long, multiplier) in prefixes {
+            let short = name
+                .strip_prefix(symbol)
+                .is_some_and(|rest| known.names.contains(&rest));
+            let long = name
+                .strip_prefix(long)
+                .is_some_and(|rest| known.long.contains(&rest));
+            if short || long {
+                return Some(unit(known, symbol, multiplier));
+            }
+        }
+    }
+
+    None
+}
+
+fn unit(known: &Known, prefix: &str, multiplier: f64) -> Unit {
+    Unit {
+        symbol: format!("{}{}", prefix, known.names[0]),
+        dimension: known.dimension,
+        scale: known.scale * multiplier,
+        offset: known.offset,
+    }
+}
+
+/// The value, in `from`, expressed in `to`.
+pub fn convert(value: f64, from: &Unit, to: &Unit) -> Result<f64> {
+    if from.dimension != to.dimension {
+        bail!("can't convert {} to {}", from.symbol, to.symbol);
+    }
+    let base = value * from.scale + from.offset;
+    Ok((base - to.offset) / to.scale)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::convert;
+    use super::lookup;
+
+    fn close(expected: f64, actual: f64) {
+        assert!(
+            (expected - actual).abs() < 1e-9,
+            "{} != {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn converting() {
+        let unit = |name| lookup(name).unwrap();
+        close(152.4, convert(5., &unit("feet"), &unit("cm")).unwrap());
+        close(
+            1.609344,
+            convert(1., &unit("mile"), &unit("kilometres")).unwrap(),
+        );
+        close(-40., convert(-40., &unit("°C"), &unit("F")).unwrap());
+        close(
+            373.15,
+            convert(212., &unit("fahrenheit"), &unit("K")).unwrap(),
+        );
+        close(1073.741824, convert(1., &unit("GiB"), &unit("MB")).unwrap());
+        close(1., convert(8., &unit("kbit"), &unit("kB")).unwrap());
+        assert_eq!("kg", unit("kilograms").symbol);
+        assert!(convert(1., &unit("kg"), &unit("m")).is_err());
+        assert!(lookup("Kim").is_none());
+        assert!(lookup("kft").is_none());
+        assert!(lookup("KiC").is_none());
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::HashSet;
+
+use anyhow::Result;
+use anyhow::bail;
+use irc::client::prelude as ic;
+use irc::proto::CapSubCommand;
+use irc::proto::caps::NegotiationVersion;
+
+use crate::config::Server;
+use crate::sasl;
+
+/// The IRCv3 capabilities we make use of, when the server offers them.
+const WANTED: &[&str] = &[
+    // `msgid` on messages, and `+draft/reply` on ours
+    "message-tags",
+    "account-tag",
+    // joins say who's logged in as what
+    "extended-join",
+    "server-time",
+    "echo-message",
+    // labeled-response wraps some replies in batches
+    "batch",
+    "labeled-response",
+];
+
+/// Capability negotiation for one connection, from `CAP LS` to `CAP END`.
+#[derive(Default)]
+pub struct Negotiation {
+    offered: HashSet<String>,
+    finished: bool,
+}
+
+/// Registers with the server, asking what it supports first.
+pub fn identify(client: &ic::Client, server: &Server) -> Result<()> {
+    // identify() would send CAP END, which has to wait until we've negotiated
+    client.send_cap_ls(NegotiationVersion::V302)?;
+    if let Some(password) = &server.password {
+        client.send(ic::Command::PASS(password.to_string()))?;
+    }
+    client.send(ic::Command::NICK(server.nick.to_string()))?;
+    client.send(ic::Command::USER(
+        server.user.as_ref().unwrap_or(&server.nick).to_string(),
+        "0".to_string(),
+        server
+            .real_name
+            .as_ref()
+            .unwrap_or(&server.nick)
+            .to_string(),
+    ))?;
+    Ok(())
+}
+
+impl Negotiation {
+    /// Progresses negotiation; returns the capabilities the server has just enabled.
+    ///
+    /// An error means we're never going to get in.
+    pub fn handle(
+        &mut self,
+        client: &ic::Client,
+        server: &Server,
+        message: &ic::Message,
+    ) -> Result<Vec<String>> {
+        sasl::handle(client, server, message)?;
+
+        if let ic::Command::Response(ic::Response::RPL_WELCOME, _) = message.command {
+            // servers without CAP support just carry on with registration
+            self.finished = true;
+        }
+
+        if self.finished {
+            return Ok(Vec::new());
+        }
+
+        let (sub, caps, more) = match &message.command {
+            ic::Command::CAP(_, sub, Some(marker), Some(caps)) => (sub, caps, marker == "*"),
+            ic::Command::CAP(_, sub, Some(caps), None) => (sub, caps, false),
+            _ => return Ok(Vec::new()),
+        };
+
+        match sub {
+            CapSubCommand::LS => {
+                // 302 servers may add values, e.g. `sasl=PLAIN,EXTERNAL`
+                self.offered.extend(
+                    caps.split_whitespace()
+                        .map(|cap| cap.split('=').next().unwrap_or(cap).to_string()),
+                );
+                if !more {
+                    self.request(client, server)?;
+                }
+            }
+            CapSubCommand::ACK => {
+                let enabled: Vec<String> = caps.split_whitespace().map(str::to_string).collect();
+                info!("enabled capabilities: {:?}", enabled);
+                match &server.sasl {
+                    Some(sasl) if enabled.iter().any(|cap| cap == "sasl") => {
+                        sasl::begin(client, sasl)?
+                    }
+                    _ => end(client)?,
+                }
+                return Ok(enabled);
+            }
+            CapSubCommand::NAK => {
+                if server.sasl.is_some() {
+                    bail!("server refused capabilities, including sasl: {:?}", caps);
+                }
+                warn!("server refused capabilities: {:?}", caps);
+                end(client)?;
+            }
+            _ => (),
+        }
+
+        Ok(Vec::new())
+    }
+
+    fn request(&self, client: &ic::Client, server: &Server) -> Result<()> {
+        let requested = to_request(&self.offered, server.sasl.is_some())?;
+        if requested.is_empty() {
+            return end(client);
+        }
+
+        client.send(ic::Command::CAP(
+            None,
+            CapSubCommand::REQ,
+            None,
+            Some(requested.join(" ")),
+        ))?;
+        Ok(())
+    }
+}
+
+fn to_request(offered: &HashSet<String>, sasl: bool) -> Result<Vec<&'static str>> {
+    let mut requested: Vec<&str> = WANTED
+        .iter()
+        .copied()
+        .filter(|cap| offered.contains(*cap))
+        .collect();
+
+    if sasl {
+        if !offered.contains("sasl") {
+            bail!("server doesn't offer sasl");
+        }
+        requested.push("sasl");
+    }
+
+    Ok(requested)
+}
+
+fn end(client: &ic::Client) -> Result<()> {
+    client.send(ic::Command::CAP(None, CapSubCommand::END, None, None))?;
+    Ok(())
+}
+
+/// The value of a message tag, if it's present.
+pub fn tag<'m>(message: &'m ic::Message, name: &str) -> Option<&'m str> {
+    message
+        .tags
+        .as_ref()?
+        .iter()
+        .find(|tag| tag.0 == name)
+        .and_then(|tag| tag.1.as_deref())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::to_request;
+
+    #[test]
+    fn requesting() {
+        let offered: HashSet<String> = ["sasl", "server-time", "away-notify", "echo-message"]
+            .iter()
+            .map(|cap| cap.to_string())
+            .collect();
+        assert_eq!(
+            vec!["server-time", "echo-message"],
+            to_request(&offered, false).unwrap()
+        );
+        assert_eq!(
+            vec!["server-time", "echo-message", "sasl"],
+            to_request(&offered, true).unwrap()
+        );
+        assert!(to_request(&HashSet::new(), true).is_err());
+        assert!(to_request(&HashSet::new(), false).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,361 @@
+//! Times in other places: `15:00 UTC in Berlin`, `tomorrow 9am to bob`, or what time it is for
+//! everyone with a configured home zone.
+
+mod zones;
+
+use anyhow::Result;
+use anyhow::bail;
+use anyhow::format_err;
+use chrono::DateTime;
+use chrono::Days;
+use chrono::NaiveDate;
+use chrono::NaiveTime;
+use chrono::TimeDelta;
+use chrono::Utc;
+use regex::Regex;
+use time_parse::duration;
+
+pub use self::zones::Zone;
+pub use self::zones::lookup as zone;
+use crate::config;
+
+lazy_static::lazy_static! {
+    static ref TIME: Regex =
+        Regex::new(r"^(?i)(\d{1,2})(?::(\d{2}))?(?::(\d{2}))?(am|pm)?$").unwrap();
+    static ref SHIFT: Regex = Regex::new(r"^(?i)([+-])((?:\d+[wdhms])+)$").unwrap();
+}
+
+/// Further than anyone means, and well short of where the arithmetic overflows.
+const MAX_SHIFT: TimeDelta = TimeDelta::days(100 * 366);
+
+/// Converts a time between zones, shows the time somewhere, or, with no arguments, for everyone
+/// with a home zone.
+pub fn answer(
+    config: &config::Time,
+    account: Option<&str>,
+    nick: &str,
+    args: &str,
+    now: DateTime<Utc>,
+) -> Result<String> {
+    let own = own(config, account, nick)?;
+
+    let args = args.trim();
+    if args.is_empty() {
+        return Ok(everyone(config, own, now));
+    }
+
+    // the last, so `15:00 in London to Berlin` is Berlin's time
+    let (source, target) = match ["in ", "to "]
+        .iter()
+        .find_map(|keyword| args.strip_prefix(keyword))
+    {
+        Some(target) => ("", Some(target)),
+        None => [" in ", " to "]
+            .iter()
+            .filter_map(|keyword| args.rsplit_once(keyword))
+            .max_by_key(|(source, _)| source.len())
+            .map_or((args, None), |(source, target)| (source, Some(target))),
+    };
+
+    let (when, place) = When::parse(source)?;
+    let from = match place.strip_prefix("in ").unwrap_or(&place) {
+        "" => own,
+        place => somewhere(config, place)?,
+    };
+    let instant = when.resolve(from, now)?;
+
+    Ok(match target {
+        Some(target) => {
+            let to = somewhere(config, target)?;
+            format!("{} = {}", show(from, instant), show(to, instant))
+        }
+        None if when.is_now() || from == own => show(from, instant),
+        None => format!("{} = {}", show(from, instant), show(own, instant)),
+    })
+}
+
+/// When something said at the start of `text` happens, and the rest of `text`:
+/// `17:00 Europe/London standup`, or `tomorrow 9am call mum`, in the person's own zone. A time
+/// which has already passed today means tomorrow.
+pub fn upcoming(
+    config: &config::Time,
+    account: Option<&str>,
+    nick: &str,
+    text: &str,
+    now: DateTime<Utc>,
+) -> Result<(DateTime<Utc>, String)> {
+    let (mut when, rest) = When::parse(text)?;
+    if when.is_now() {
+        bail!("When? Try a time like 17:00 or tomorrow 9am.");
+    }
+
+    // a zone can be a few words, `new york`; whatever's left is theirs
+    let words = rest.split_whitespace().collect::<Vec<_>>();
+    let (zone, rest) = (1..=words.len().min(3))
+        .rev()
+        .find_map(|count| {
+            let name = words[..count].join(" ");
+            let name = name.strip_prefix("in ").unwrap_or(&name);
+            zone(name).map(|zone| (zone, words[count..].join(" ")))
+        })
+        .map_or_else(|| own(config, account, nick).map(|own| (own, rest)), Ok)?;
+
+    let mut instant = when.resolve(zone, now)?;
+    if instant <= now && when.date.is_none() && 0 == when.days && when.time.is_some() {
+        when.days = 1;
+        instant = when.resolve(zone, now)?;
+    }
+    Ok((instant, rest))
+}
+
+/// How long `2h30m` or `1w` is, if that's what it looks like; an error if it's absurdly long.
+pub fn delay(text: &str) -> Option<Result<TimeDelta>> {
+    let signed = format!("+{}", text);
+    let delay = SHIFT.captures(&signed)?;
+    Some(parse_shift(&delay[1], &delay[2]))
+}
+
+/// A moment, on the person's own clock.
+pub fn show_for(
+    config: &config::Time,
+    account: Option<&str>,
+    nick: &str,
+    instant: DateTime<Utc>,
+) -> Result<String> {
+    Ok(show(own(config, account, nick)?, instant))
+}
+
+/// The zone for someone: their home, by account or nick, or the configured default.
+fn own(config: &config::Time, account: Option<&str>, nick: &str) -> Result<Zone> {
+    account
+        .and_then(|account| home(config, account))
+        .or_else(|| home(config, nick))
+        .or_else(|| zone(&config.zone))
+        .ok_or_else(|| format_err!("invalid [time] zone"))
+}
+
+/// Someone's home zone, by nick or account.
+fn home(config: &config::Time, who: &str) -> Option<Zone> {
+    config
+        .homes
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(who))
+        .and_then(|(_, zone)| self::zone(zone))
+}
+
+/// A person's home, or a zone.
+fn somewhere(config: &config::Time, name: &str) -> Result<Zone> {
+    let name = name.trim();
+    home(config, name)
+        .or_else(|| zone(name))
+        .ok_or_else(|| format_err!("Unknown time zone: {}", name))
+}
+
+/// Everyone's time now, earliest first, with people in the same zone together.
+fn everyone(config: &config::Time, own: Zone, now: DateTime<Utc>) -> String {
+    let mut places: Vec<(Zone, Vec<&str>)> = Vec::new();
+    for (who, name) in &config.homes {
+        let Some(zone) = zone(name) else {
+            continue;
+        };
+        match places.iter_mut().find(|(place, _)| *place == zone) {
+            Some((_, people)) => people.push(who),
+            None => places.push((zone, vec![who])),
+        }
+    }
+
+    if places.is_empty() {
+        return show(own, now);
+    }
+
+    places.sort_by_key(|(zone, _)| zone.local(now).0);
+    places
+        .iter()
+        .map(|(zone, people)| format!("{}: {}", people.join(", "), show(*zone, now)))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+fn show(zone: Zone, instant: DateTime<Utc>) -> String {
+    let (local, abbreviation) = zone.local(instant);
+    let name = zone.name();
+    let shown = format!("{} {}", local.format("%a %-d %b %H:%M"), abbreviation);
+    match name == abbreviation {
+        true => shown,
+        false => format!("{} ({})", shown, name),
+    }
+}
+
+/// A time, in some zone which isn't known yet: `tomorrow 3pm`, `2024-06-01 09:30`, `+2h`.
+#[derive(Debug, Default)]
+struct When {
+    date: Option<NaiveDate>,
+    days: i64,
+    time: Option<NaiveTime>,
+    shift: TimeDelta,
+}
+
+impl When {
+    /// The time, and whatever follows it, which should be a place.
+    fn parse(text: &str) -> Result<(When, String)> {
+        let mut when = When::default();
+        let mut words = text.split_whitespace().peekable();
+
+        while let Some(&word) = words.peek() {
+            let lower = word.to_lowercase();
+            match lower.as_str() {
+                "now" | "at" => (),
+                "today" => when.days = 0,
+                "tomorrow" => when.days = 1,
+                "yesterday" => when.days = -1,
+                "noon" | "midday" => when.time = NaiveTime::from_hms_opt(12, 0, 0),
+                "midnight" => when.time = NaiveTime::from_hms_opt(0, 0, 0),
+                _ => {
+                    if let Ok(date) = NaiveDate::parse_from_str(word, "%Y-%m-%d") {
+                        when.date = Some(date);
+                    } else if let Some(shift) = SHIFT.captures(word) {
+                        when.shift = when
+                            .shift
+                            .checked_add(&parse_shift(&shift[1], &shift[2])?)
+                            .filter(|shift| shift.abs() <= MAX_SHIFT)
+                            .ok_or_else(too_far)?;
+                    } else if let Some(time) = TIME.captures(word) {
+                        words.next();
+                        // `3 pm`, as well as `3pm`
+                        let meridiem = match time.get(4) {
+                            Some(meridiem) => Some(meridiem.as_str().to_lowercase()),
+                            None => words
+                                .next_if(|next| {
+                                    next.eq_ignore_ascii_case("am")
+                                        || next.eq_ignore_ascii_case("pm")
+                                })
+                                .map(str::to_lowercase),
+                        };
+                        // a bare number could mean anything; `3pm` and `15:00` don't
+                        if time.get(2).is_none() && meridiem.is_none() {
+                            bail!("Not a time: {} (try {}:00)", word, word);
+                        }
+                        when.time = Some(parse_time(&time, meridiem.as_deref())?);
+                        continue;
+                    } else {
+                        break;
+                    }
+                }
+            }
+            words.next();
+        }
+
+        Ok((when, words.collect::<Vec<_>>().join(" ")))
+    }
+
+    fn is_now(&self) -> bool {
+        self.date.is_none() && 0 == self.days && self.time.is_none() && self.shift.is_zero()
+    }
+
+    /// The moment, if the time was in `zone`.
+    fn resolve(&self, zone: Zone, now: DateTime<Utc>) -> Result<DateTime<Utc>> {
+        if self.date.is_none() && 0 == self.days && self.time.is_none() {
+            return now.checked_add_signed(self.shift).ok_or_else(too_far);
+        }
+
+        let (local, _) = zone.local(now);
+        let date = self.date.unwrap_or(local.date());
+        let date = match self.days {
+            days if days < 0 => date.checked_sub_days(Days::new(days.unsigned_abs())),
+            days => date.checked_add_days(Days::new(days as u64)),
+        }
+        .ok_or_else(too_far)?;
+        let local = date.and_time(self.time.unwrap_or(local.time()));
+
+        match zone.resolve(local) {
+            Some(instant) => instant.checked_add_signed(self.shift).ok_or_else(too_far),
+            None => bail!(
+                "{} doesn't happen in {}; the clocks change",
+                local.format("%Y-%m-%d %H:%M"),
+                zone.name()
+            ),
+        }
+    }
+}
+
+fn too_far() -> anyhow::Error {
+    format_err!("That's too far away.")
+}
+
+fn parse_time(time: &regex::Captures, meridiem: Option<&str>) -> Result<NaiveTime> {
+    let number = |index| time.get(index).map_or(Ok(0), |m| m.as_str().parse::<u32>());
+    let (hour, minute, second) = (number(1)?, number(2)?, number(3)?);
+    let hour = match meridiem {
+        Some(_) if !(1..=12).contains(&hour) => bail!("Not a time: {}{}", hour, meridiem.unwrap()),
+        Some("am") => hour % 12,
+        Some(_) => hour % 12 + 12,
+        None => hour,
+    };
+    NaiveTime::from_hms_opt(hour, minute, second)
+        .ok_or_else(|| format_err!("Not a time: {}", &time[0]))
+}
+
+/// `+1d2h` or `-90m`, as an ISO-8601 period for time-parse: `P1DT2H`.
+fn parse_shift(sign: &str, amount: &str) -> Result<TimeDelta> {
+    // time-parse doesn't check its arithmetic, so the numbers are kept small
+    if amount
+        .split(char::is_alphabetic)
+        .any(|digits| digits.len() > 6)
+    {
+        return Err(too_far());
+    }
+    let amount = amount.to_uppercase();
+    let split = amount.rfind(['W', 'D']).map_or(0, |end| end + 1);
+    let period = format!("P{}T{}", &amount[..split], &amount[split..]);
+    let shift = TimeDelta::from_std(duration::parse(&period)?)?;
+    if shift > MAX_SHIFT {
+        return Err(too_far());
+    }
+    Ok(match sign {
+        "-" => -shift,
+        _ => shift,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+
+    use super::answer;
+    use crate::config;
+
+    #[test]
+    fn converting() {
+        let mut config = config::Time::default();
+        config
+            .homes
+            .insert("faux".to_string(), "Europe/London".to_string());
+        config
+            .homes
+            .insert("bob".to_string(), "America/New_York".to_string());
+        let now = DateTime::parse_from_rfc3339("2026-10-19T12:00:00Z")
+            .unwrap()
+            .to_utc();
+        let time = |args| answer(&config, None, "faux", args, now).unwrap();
+
+        assert_eq!(
+            "Mon 19 Oct 15:00 UTC = Mon 19 Oct 17:00 CEST (Europe/Berlin)",
+            time("15:00 UTC in Berlin")
+        );
+        assert_eq!(
+            "Tue 20 Oct 09:00 BST (Europe/London) = Tue 20 Oct 04:00 EDT (America/New_York)",
+            time("tomorrow 9am to bob")
+        );
+        assert_eq!("Mon 19 Oct 21:00 JST (Asia/Tokyo)", time("tokyo"));
+        assert_eq!("Mon 19 Oct 16:30 BST (Europe/London)", time("+3h30m"));
+        assert_eq!(
+            "bob: Mon 19 Oct 08:00 EDT (America/New_York); \
+             faux: Mon 19 Oct 13:00 BST (Europe/London)",
+            time("")
+        );
+        assert!(answer(&config, None, "faux", "3pm in Atlantis", now).is_err());
+        for far in ["+100000000w", "+99999w +99999w", "+9999999999999999999h"] {
+            assert!(answer(&config, None, "faux", far, now).is_err(), "{}", far);
+        }
+    }
+}
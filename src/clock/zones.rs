@@ -0,0 +1,148 @@
+use chrono::DateTime;
+use chrono::FixedOffset;
+use chrono::NaiveDateTime;
+use chrono::TimeZone;
+use chrono::Utc;
+use chrono_tz::TZ_VARIANTS;
+use chrono_tz::Tz;
+use regex::Regex;
+
+lazy_static::lazy_static! {
+    static ref OFFSET: Regex =
+        Regex::new(r"^(?i:utc|gmt)?([+-])(\d{1,2})(?::?(\d{2}))?$").unwrap();
+}
+
+/// What people say, for the zone they mean; summer and winter names go to the same place.
+const ABBREVIATIONS: &[(&str, &str)] = &[
+    ("UTC", "UTC"),
+    ("Z", "UTC"),
+    ("GMT", "Etc/GMT"),
+    ("BST", "Europe/London"),
+    ("WET", "Europe/Lisbon"),
+    ("WEST", "Europe/Lisbon"),
+    ("CET", "Europe/Berlin"),
+    ("CEST", "Europe/Berlin"),
+    ("EET", "Europe/Helsinki"),
+    ("EEST", "Europe/Helsinki"),
+    ("MSK", "Europe/Moscow"),
+    ("IST", "Asia/Kolkata"),
+    ("SGT", "Asia/Singapore"),
+    ("HKT", "Asia/Hong_Kong"),
+    ("JST", "Asia/Tokyo"),
+    ("KST", "Asia/Seoul"),
+    ("AEST", "Australia/Sydney"),
+    ("AEDT", "Australia/Sydney"),
+    ("NZST", "Pacific/Auckland"),
+    ("NZDT", "Pacific/Auckland"),
+    ("HST", "Pacific/Honolulu"),
+    ("AKST", "America/Anchorage"),
+    ("AKDT", "America/Anchorage"),
+    ("PT", "America/Los_Angeles"),
+    ("PST", "America/Los_Angeles"),
+    ("PDT", "America/Los_Angeles"),
+    ("MT", "America/Denver"),
+    ("MST", "America/Denver"),
+    ("MDT", "America/Denver"),
+    ("CT", "America/Chicago"),
+    ("CST", "America/Chicago"),
+    ("CDT", "America/Chicago"),
+    ("ET", "America/New_York"),
+    ("EST", "America/New_York"),
+    ("EDT", "America/New_York"),
+];
+
+/// Somewhere clocks agree.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Zone {
+    Named(Tz),
+    Fixed(FixedOffset),
+}
+
+/// A zone by IANA name (`Europe/Berlin`), city (`berlin`, `new york`), common abbreviation
+/// (`CEST`), or offset (`UTC+2`, `+05:30`).
+pub fn lookup(name: &str) -> Option<Zone> {
+    let name = name.trim();
+    if let Some(&(_, zone)) = ABBREVIATIONS
+        .iter()
+        .find(|(abbreviation, _)| abbreviation.eq_ignore_ascii_case(name))
+    {
+        return zone.parse().ok().map(Zone::Named);
+    }
+
+    if let Some(offset) = OFFSET.captures(name) {
+        let hours: i32 = offset[2].parse().ok()?;
+        let minutes: i32 = offset.get(3).map_or(Some(0), |m| m.as_str().parse().ok())?;
+        let seconds = (hours * 60 + minutes) * 60;
+        let seconds = if &offset[1] == "-" { -seconds } else { seconds };
+        return FixedOffset::east_opt(seconds).map(Zone::Fixed);
+    }
+
+    let wanted = name.replace(' ', "_");
+    TZ_VARIANTS
+        .iter()
+        .find(|tz| tz.name().eq_ignore_ascii_case(&wanted))
+        .or_else(|| {
+            TZ_VARIANTS.iter().find(|tz| {
+                tz.name()
+                    .rsplit('/')
+                    .next()
+                    .is_some_and(|city| city.eq_ignore_ascii_case(&wanted))
+            })
+        })
+        .map(|&tz| Zone::Named(tz))
+}
+
+impl Zone {
+    /// The wall-clock time here at that instant, and what the zone is called then, e.g. `CEST`.
+    pub fn local(&self, instant: DateTime<Utc>) -> (NaiveDateTime, String) {
+        match self {
+            Zone::Named(tz) => {
+                let local = instant.with_timezone(tz);
+                (local.naive_local(), local.format("%Z").to_string())
+            }
+            Zone::Fixed(offset) => (instant.with_timezone(offset).naive_local(), self.name()),
+        }
+    }
+
+    /// When a wall-clock time here happens; the earlier, if the clocks went back past it, and
+    /// `None` if they skipped it.
+    pub fn resolve(&self, local: NaiveDateTime) -> Option<DateTime<Utc>> {
+        let instant = match self {
+            Zone::Named(tz) => tz.from_local_datetime(&local).earliest()?.to_utc(),
+            Zone::Fixed(offset) => offset.from_local_datetime(&local).earliest()?.to_utc(),
+        };
+        Some(instant)
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            Zone::Named(tz) => tz.name().to_string(),
+            Zone::Fixed(offset) => format!("UTC{}", offset),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::FixedOffset;
+
+    use super::Zone;
+    use super::lookup;
+
+    #[test]
+    fn looking_up() {
+        let named = |name: &str| Zone::Named(name.parse().unwrap());
+        assert_eq!(Some(named("Europe/Berlin")), lookup("europe/berlin"));
+        assert_eq!(Some(named("Europe/Berlin")), lookup("Berlin"));
+        assert_eq!(Some(named("America/New_York")), lookup("new york"));
+        assert_eq!(Some(named("America/Los_Angeles")), lookup("pst"));
+        assert_eq!(
+            Some(Zone::Fixed(
+                FixedOffset::east_opt(-(5 * 60 + 30) * 60).unwrap()
+            )),
+            lookup("UTC-05:30")
+        );
+        assert_eq!(None, lookup("Atlantis"));
+        assert_eq!(None, lookup("+99"));
+    }
+}
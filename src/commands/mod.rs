@@ -0,0 +1,653 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::iter;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Result;
+use anyhow::bail;
+use chrono::Utc;
+use reqwest::Client;
+
+use crate::admin;
+use crate::calc;
+use crate::clock;
+use crate::config;
+use crate::config::ChannelSettings;
+use crate::db;
+use crate::transport::Outbox;
+use crate::webs::Context;
+
+/// Who's asking, as far as the network can tell us.
+pub struct Caller<'a> {
+    pub network: &'a str,
+    pub nick: &'a str,
+    pub account: Option<&'a str>,
+    pub hostmask: Option<&'a str>,
+}
+
+/// What a command does, once it's been recognised.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Help,
+    Qalc,
+    Time,
+    Seen,
+    Tell,
+    Remind,
+    Url,
+    /// handled by `admin`, for operators only
+    Admin,
+    /// the `[[tool]]` at this index, run by `Context::tools`
+    Tool(usize),
+}
+
+/// A command which is always there, before configuration.
+struct Builtin {
+    name: &'static str,
+    aliases: &'static [&'static str],
+    usage: &'static str,
+    help: &'static str,
+    min_args: usize,
+    kind: Kind,
+}
+
+const BUILTIN: &[Builtin] = &[
+    Builtin {
+        name: "help",
+        aliases: &[],
+        usage: "[command]",
+        help: "lists the commands, or explains one",
+        min_args: 0,
+        kind: Kind::Help,
+    },
+    Builtin {
+        name: "qalc",
+        aliases: &["calc"],
+        usage: "<expression>",
+        help: "calculates, e.g. 5 feet in cm",
+        min_args: 1,
+        kind: Kind::Qalc,
+    },
+    Builtin {
+        name: "time",
+        aliases: &[],
+        usage: "[time] [zone] [in <zone or nick>]",
+        help: "converts times between zones, e.g. 15:00 UTC in Berlin; alone, shows everyone's time",
+        min_args: 0,
+        kind: Kind::Time,
+    },
+    Builtin {
+        name: "seen",
+        aliases: &[],
+        usage: "<nick> | off | on",
+        help: "says when someone was last around, and where; off stops it remembering you",
+        min_args: 1,
+        kind: Kind::Seen,
+    },
+    Builtin {
+        name: "tell",
+        aliases: &[],
+        usage: "<nick> <message>",
+        help: "passes a message on when they next speak or join",
+        min_args: 2,
+        kind: Kind::Tell,
+    },
+    Builtin {
+        name: "remind",
+        aliases: &[],
+        usage: "(me | #chan) (in 2h30m | at 17:00 [zone]) <text> | list | cancel <number>",
+        help: "sends a reminder later, to you or the channel",
+        min_args: 1,
+        kind: Kind::Remind,
+    },
+    Builtin {
+        name: "url",
+        aliases: &[],
+        usage: "search <words> | last [nick]",
+        help: "finds links posted here, by their titles",
+        min_args: 1,
+        kind: Kind::Url,
+    },
+    Builtin {
+        name: "join",
+        aliases: &[],
+        usage: "#chan",
+        help: "joins a channel",
+        min_args: 0,
+        kind: Kind::Admin,
+    },
+    Builtin {
+        name: "part",
+        aliases: &[],
+        usage: "#chan [reason]",
+        help: "leaves a channel",
+        min_args: 0,
+        kind: Kind::Admin,
+    },
+    Builtin {
+        name: "say",
+        aliases: &[],
+        usage: "#chan text",
+        help: "says something in a channel",
+        min_args: 0,
+        kind: Kind::Admin,
+    },
+    Builtin {
+        name: "mute",
+        aliases: &[],
+        usage: "#chan",
+        help: "stops replying in a channel",
+        min_args: 0,
+        kind: Kind::Admin,
+    },
+    Builtin {
+        name: "unmute",
+        aliases: &[],
+        usage: "#chan",
+        help: "replies in a channel again",
+        min_args: 0,
+        kind: Kind::Admin,
+    },
+    Builtin {
+        name: "reload",
+        aliases: &[],
+        usage: "",
+        help: "reloads rules, scripts and link cleaning",
+        min_args: 0,
+        kind: Kind::Admin,
+    },
+    Builtin {
+        name: "clear",
+        aliases: &[],
+        usage: "",
+        help: "forgets titles, rate limits and api tokens",
+        min_args: 0,
+        kind: Kind::Admin,
+    },
+    Builtin {
+        name: "status",
+        aliases: &[],
+        usage: "",
+        help: "shows how each title source is doing",
+        min_args: 0,
+        kind: Kind::Admin,
+    },
+    Builtin {
+        name: "quit",
+        aliases: &[],
+        usage: "[reason]",
+        help: "disconnects from every network",
+        min_args: 0,
+        kind: Kind::Admin,
+    },
+];
+
+/// Something people can ask the bot to do.
+pub struct Command {
+    pub name: String,
+    pub aliases: Vec<String>,
+    /// what goes after the name
+    pub usage: String,
+    pub help: String,
+    /// words of arguments needed; with fewer, the usage is shown instead
+    min_args: usize,
+    /// between uses by the same person
+    cooldown: Duration,
+    kind: Kind,
+}
+
+impl Command {
+    /// Only for operators, and only in private.
+    pub fn is_admin(&self) -> bool {
+        self.kind == Kind::Admin
+    }
+}
+
+/// Every command, with the configured aliases and cooldowns.
+pub struct Registry {
+    commands: Vec<Command>,
+    /// (network, lower-cased nick, command) -> when they last used it
+    used: Mutex<HashMap<(String, String, String), Instant>>,
+}
+
+/// Cooldowns are forgotten after this, if there are a lot of them.
+const COOLDOWN_MEMORY: Duration = Duration::from_secs(60 * 60);
+
+impl Registry {
+    pub fn new(config: &config::Config) -> Result<Registry> {
+        let settings = &config.commands;
+
+        let builtin = BUILTIN.iter().map(|builtin| Command {
+            name: builtin.name.to_string(),
+            aliases: builtin.aliases.iter().map(|a| a.to_string()).collect(),
+            usage: builtin.usage.to_string(),
+            help: builtin.help.to_string(),
+            min_args: builtin.min_args,
+            cooldown: Duration::ZERO,
+            kind: builtin.kind,
+        });
+        let tools = config
+            .tools
+            .iter()
+            .enumerate()
+            .map(|(index, tool)| Command {
+                name: tool.name.to_string(),
+                aliases: tool.aliases.clone(),
+                usage: tool.usage.to_string(),
+                help: match tool.help.as_str() {
+                    "" => format!("runs {}", tool.command.join(" ")),
+                    help => help.to_string(),
+                },
+                min_args: tool.min_args,
+                cooldown: Duration::ZERO,
+                kind: Kind::Tool(index),
+            });
+        let mut commands: Vec<Command> = builtin.chain(tools).collect();
+
+        for (alias, name) in &settings.aliases {
+            let Some(command) = commands.iter_mut().find(|command| command.name == *name) else {
+                bail!(
+                    "[commands] alias {:?} is for unknown command {:?}",
+                    alias,
+                    name
+                );
+            };
+            command.aliases.push(alias.to_string());
+        }
+
+        if let Some(name) = settings
+            .cooldowns
+            .keys()
+            .find(|name| !commands.iter().any(|command| command.name == **name))
+        {
+            bail!("[commands] cooldown for unknown command {:?}", name);
+        }
+
+        let mut names = HashSet::new();
+        for command in &mut commands {
+            command.aliases.sort();
+            for name in iter::once(&command.name).chain(&command.aliases) {
+                if !names.insert(name.to_lowercase()) {
+                    bail!("command or alias {:?} is defined twice", name);
+                }
+            }
+            if !command.is_admin() {
+                command.cooldown = Duration::from_secs(
+                    *settings
+                        .cooldowns
+                        .get(&command.name)
+                        .unwrap_or(&settings.cooldown),
+                );
+            }
+        }
+
+        Ok(Registry {
+            commands,
+            used: Mutex::default(),
+        })
+    }
+
+    /// By name or alias.
+    pub fn find(&self, name: &str) -> Option<&Command> {
+        self.commands.iter().find(|command| {
+            command.name.eq_ignore_ascii_case(name)
+                || command.aliases.iter().any(|a| a.eq_ignore_ascii_case(name))
+        })
+    }
+
+    /// Records a use of the command, unless it's too soon after the last one.
+    pub fn cooled_down(&self, network: &str, nick: &str, command: &Command) -> bool {
+        let key = (
+            network.to_string(),
+            nick.to_lowercase(),
+            command.name.to_string(),
+        );
+        let mut used = self.used.lock().expect("poisoned");
+        if let Some(when) = used.get(&key)
+            && when.elapsed() < command.cooldown
+        {
+            return false;
+        }
+
+        if used.len() > 1000 {
+            used.retain(|_, when| when.elapsed() < COOLDOWN_MEMORY);
+        }
+        used.insert(key, Instant::now());
+        true
+    }
+}
+
+/// The command name and its arguments, if the text is a command.
+pub fn parse<'t>(
+    config: &config::Commands,
+    nick: &str,
+    text: &'t str,
+) -> Option<(&'t str, &'t str)> {
+    let text = text.trim();
+    let text = match text.strip_prefix(config.prefix.as_str()) {
+        Some(text) => text,
+        None if config.addressed => {
+            // "unsnap: qalc 1+1", or "unsnap, !qalc 1+1"
+            let rest = text
+                .get(..nick.len())
+                .filter(|start| start.eq_ignore_ascii_case(nick))
+                .and_then(|_| text[nick.len()..].strip_prefix([':', ',']))?
+                .trim_start();
+            rest.strip_prefix(config.prefix.as_str()).unwrap_or(rest)
+        }
+        None => return None,
+    };
+
+    let (name, args) = text.split_once(' ').unwrap_or((text, ""));
+    if name.is_empty() {
+        return None;
+    }
+    Some((name, args.trim()))
+}
+
+/// Splits arguments at spaces, except inside double quotes.
+pub fn words(args: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+    for c in args.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            ' ' if !quoted => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+/// Whether the command is answered here; admin commands are only ever answered in private.
+pub fn available(
+    context: &Context,
+    command: &Command,
+    settings: &ChannelSettings,
+    private: bool,
+) -> bool {
+    if command.is_admin() {
+        return private;
+    }
+
+    settings.commands
+        && !settings
+            .disabled_commands
+            .iter()
+            .any(|disabled| disabled.eq_ignore_ascii_case(&command.name))
+        && (!private || context.config.query.commands.contains(&command.name))
+}
+
+/// A recognised command, and everything about where it came from.
+pub struct Invocation<'a> {
+    pub caller: Caller<'a>,
+    pub command: &'a Command,
+    pub args: &'a str,
+    pub settings: &'a ChannelSettings,
+    pub private: bool,
+    /// where replies go: the channel, or the caller, in private
+    pub reply_to: &'a str,
+}
+
+/// Runs the command; returns the replies.
+pub async fn run(
+    http: &Client,
+    context: &Arc<Context>,
+    outbox: &dyn Outbox,
+    invocation: &Invocation<'_>,
+) -> Result<Vec<String>> {
+    let command = invocation.command;
+    let args = invocation.args;
+    if words(args).len() < command.min_args {
+        return Ok(vec![format!("Usage: {}", usage(context, command))]);
+    }
+
+    Ok(match command.kind {
+        Kind::Help => help(context, invocation),
+        Kind::Qalc => match context
+            .qalc
+            .run(invocation.caller.network, invocation.caller.nick, args)
+            .await
+        {
+            Ok(Some(output)) => vec![output],
+            Ok(None) => vec![format!("{} (built-in calculator)", calc::answer(args))],
+            Err(e) => failed(&command.name, args, e),
+        },
+        Kind::Time => {
+            let caller = &invocation.caller;
+            vec![match clock::answer(
+                &context.config.time,
+                caller.account,
+                caller.nick,
+                args,
+                Utc::now(),
+            ) {
+                Ok(answer) => answer,
+                Err(e) => e.to_string(),
+            }]
+        }
+        Kind::Seen => {
+            let caller = &invocation.caller;
+            let (context, network, nick, query) = owned(context, caller, args);
+            let channel = (!invocation.private).then(|| invocation.reply_to.to_string());
+            let answer = db::blocking(move || {
+                context
+                    .seen
+                    .answer(&network, channel.as_deref(), &nick, &query, Utc::now())
+            });
+            match answer.await {
+                Ok(answer) => vec![answer],
+                Err(e) => failed(&command.name, args, e),
+            }
+        }
+        Kind::Tell => {
+            let caller = &invocation.caller;
+            let (context, network, nick, message) = owned(context, caller, args);
+            let account = caller.account.map(str::to_string);
+            let answer = db::blocking(move || {
+                context
+                    .tells
+                    .leave(&network, &nick, account.as_deref(), &message, Utc::now())
+            });
+            match answer.await {
+                Ok(answer) => vec![answer],
+                Err(e) => failed(&command.name, args, e),
+            }
+        }
+        Kind::Remind => {
+            let caller = &invocation.caller;
+            // anyone can remind where they are; only operators can reach other channels
+            let anywhere =
+                admin::is_operator(&context.config.admin, caller.account, caller.hostmask);
+            let (context, network, nick, request) = owned(context, caller, args);
+            let account = caller.account.map(str::to_string);
+            let here = invocation.reply_to.to_string();
+            let answer = db::blocking(move || {
+                let caller = Caller {
+                    network: &network,
+                    nick: &nick,
+                    account: account.as_deref(),
+                    hostmask: None,
+                };
+                context
+                    .reminders
+                    .answer(&caller, &here, anywhere, &request, Utc::now())
+            });
+            match answer.await {
+                Ok(answer) => vec![answer],
+                Err(e) => failed(&command.name, args, e),
+            }
+        }
+        Kind::Url => {
+            let (context, network, _, query) = owned(context, &invocation.caller, args);
+            let channel = (!invocation.private).then(|| invocation.reply_to.to_string());
+            let answer = db::blocking(move || {
+                context
+                    .urls
+                    .answer(&network, channel.as_deref(), &query, Utc::now())
+            });
+            match answer.await {
+                Ok(answer) => answer,
+                Err(e) => failed(&command.name, args, e),
+            }
+        }
+        Kind::Admin => admin::handle(
+            http,
+            context,
+            outbox,
+            &invocation.caller,
+            &command.name,
+            args,
+        ),
+        Kind::Tool(index) => match context.tools.run(index, args).await {
+            Ok(output) => vec![output],
+            Err(e) => failed(&command.name, args, e),
+        },
+    })
+}
+
+/// What the stores' commands need, to run away from the caller, on the blocking pool.
+fn owned(
+    context: &Arc<Context>,
+    caller: &Caller,
+    args: &str,
+) -> (Arc<Context>, String, String, String) {
+    (
+        Arc::clone(context),
+        caller.network.to_string(),
+        caller.nick.to_string(),
+        args.to_string(),
+    )
+}
+
+/// What's said when a command breaks; the details are only for the log.
+fn failed(name: &str, args: &str, e: anyhow::Error) -> Vec<String> {
+    error!("{} {:?} failed: {:?}", name, args, e);
+    vec!["It did not work.".to_string()]
+}
+
+fn usage(context: &Context, command: &Command) -> String {
+    format!(
+        "{}{} {}",
+        context.config.commands.prefix, command.name, command.usage
+    )
+    .trim_end()
+    .to_string()
+}
+
+fn help(context: &Context, invocation: &Invocation) -> Vec<String> {
+    let prefix = &context.config.commands.prefix;
+    let caller = &invocation.caller;
+    let usable = |command: &&Command| {
+        available(context, command, invocation.settings, invocation.private)
+            && (!command.is_admin()
+                || admin::is_operator(&context.config.admin, caller.account, caller.hostmask))
+    };
+
+    let wanted = invocation.args.trim_start_matches(prefix.as_str());
+    if !wanted.is_empty() {
+        return vec![match context.commands.find(wanted).filter(usable) {
+            Some(command) if command.aliases.is_empty() => {
+                format!("{}: {}", usage(context, command), command.help)
+            }
+            Some(command) => format!(
+                "{}: {} (also: {})",
+                usage(context, command),
+                command.help,
+                command
+                    .aliases
+                    .iter()
+                    .map(|alias| format!("{}{}", prefix, alias))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            None => format!("No such command: {}", wanted),
+        }];
+    }
+
+    let names: Vec<String> = context
+        .commands
+        .commands
+        .iter()
+        .filter(usable)
+        .map(|command| format!("{}{}", prefix, command.name))
+        .collect();
+    vec![format!(
+        "Commands: {} ({}help <command> for more)",
+        names.join(", "),
+        prefix
+    )]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Registry;
+    use super::parse;
+    use super::words;
+    use crate::config::Commands;
+    use crate::config::Config;
+
+    #[test]
+    fn parsing() {
+        let config = Commands::default();
+        assert_eq!(
+            Some(("qalc", "1 + 1")),
+            parse(&config, "unsnap", "!qalc  1 + 1")
+        );
+        assert_eq!(Some(("help", "")), parse(&config, "unsnap", "!help"));
+        assert_eq!(
+            Some(("qalc", "2")),
+            parse(&config, "unsnap", "UnSnap: qalc 2")
+        );
+        assert_eq!(
+            Some(("qalc", "2")),
+            parse(&config, "unsnap", "unsnap, !qalc 2")
+        );
+        assert_eq!(None, parse(&config, "unsnap", "unsnapper: qalc 2"));
+        assert_eq!(None, parse(&config, "unsnap", "qalc 2"));
+        assert_eq!(None, parse(&config, "unsnap", "! qalc"));
+
+        let config = Commands {
+            addressed: false,
+            ..Commands::default()
+        };
+        assert_eq!(None, parse(&config, "unsnap", "unsnap: qalc 2"));
+
+        assert_eq!(vec!["a", "b c", "d"], words(r#" a "b c"  d "#));
+    }
+
+    #[test]
+    fn registry() {
+        let mut config: Config = toml::from_str(include_str!("../../bot.toml.example")).unwrap();
+        config
+            .commands
+            .aliases
+            .insert("c".to_string(), "qalc".to_string());
+        let registry = Registry::new(&config).unwrap();
+        assert_eq!("qalc", registry.find("C").unwrap().name);
+        assert_eq!("qalc", registry.find("calc").unwrap().name);
+        assert!(registry.find("nope").is_none());
+
+        let qalc = registry.find("qalc").unwrap();
+        assert!(registry.cooled_down("net", "Faux", qalc));
+        assert!(!registry.cooled_down("net", "faux", qalc));
+        assert!(registry.cooled_down("net", "other", qalc));
+
+        config
+            .commands
+            .aliases
+            .insert("help".to_string(), "qalc".to_string());
+        assert!(Registry::new(&config).is_err());
+    }
+}
@@ -1,16 +1,185 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs;
+use std::iter;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::Context as _;
+use anyhow::Result;
+use anyhow::bail;
+use anyhow::format_err;
 use serde_derive::Deserialize;
 use serde_derive::Serialize;
 
+use crate::clock;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Config {
-    pub server: Server,
+    /// a single network, named after its hostname; `[[network]]` allows many
+    pub server: Option<Server>,
+
+    #[serde(default, rename = "network")]
+    pub networks: Vec<Network>,
+
     pub keys: Keys,
+
+    #[serde(default, rename = "rule")]
+    pub rules: Vec<Rule>,
+
+    /// directory of `.rhai` title scripts
+    pub scripts: Option<PathBuf>,
+
+    /// where runtime state (e.g. channels joined by invite) is kept; none is, without it
+    pub data_dir: Option<PathBuf>,
+
+    #[serde(default)]
+    pub clean: Clean,
+
+    #[serde(default)]
+    pub ctcp: Ctcp,
+
+    #[serde(default)]
+    pub commands: Commands,
+
+    #[serde(default)]
+    pub query: Query,
+
+    #[serde(default)]
+    pub qalc: Qalc,
+
+    #[serde(default)]
+    pub time: Time,
+
+    #[serde(default)]
+    pub seen: Seen,
+
+    #[serde(default)]
+    pub tell: Tell,
+
+    #[serde(default)]
+    pub remind: Remind,
+
+    #[serde(default)]
+    pub urls: Urls,
+
+    /// external programs, offered as commands
+    #[serde(default, rename = "tool")]
+    pub tools: Vec<Tool>,
+
+    #[serde(default)]
+    pub output: Output,
+
+    #[serde(default)]
+    pub admin: Admin,
+}
+
+/// Where the configuration lives, relative to the working directory.
+pub const PATH: &str = "bot.toml";
+
+impl Config {
+    /// Reads and checks the configuration.
+    pub fn load() -> Result<Config> {
+        let config: Config = toml::from_str(
+            &fs::read_to_string(PATH).with_context(|| format_err!("reading {}", PATH))?,
+        )
+        .with_context(|| format_err!("parsing {}", PATH))?;
+        config
+            .validate()
+            .with_context(|| format_err!("invalid {}", PATH))?;
+        Ok(config)
+    }
+
+    /// Every network to connect to, including the legacy `[server]`.
+    pub fn all_networks(&self) -> Vec<Network> {
+        let legacy = self.server.iter().map(|server| Network {
+            name: server.hostname.to_string(),
+            server: server.clone(),
+        });
+        legacy.chain(self.networks.iter().cloned()).collect()
+    }
+
+    pub fn validate(&self) -> Result<()> {
+        let networks = self.all_networks();
+        if networks.is_empty() {
+            bail!("no [server] or [[network]] configured");
+        }
+
+        let mut names = HashSet::new();
+        for network in &networks {
+            if !names.insert(&network.name) {
+                bail!("duplicate network name: {:?}", network.name);
+            }
+            network
+                .server
+                .validate()
+                .with_context(|| format_err!("network {:?}", network.name))?;
+        }
+
+        if self.commands.prefix.is_empty() || self.commands.prefix.contains(' ') {
+            bail!("[commands] prefix must be non-empty, without spaces");
+        }
+
+        if 0 == self.qalc.concurrency || 0 == self.qalc.workers {
+            bail!("[qalc] concurrency and workers must be at least one");
+        }
+
+        if self.qalc.command.is_empty() {
+            bail!("[qalc] command must not be empty");
+        }
+
+        for zone in iter::once(&self.time.zone).chain(self.time.homes.values()) {
+            if clock::zone(zone).is_none() {
+                bail!("[time] unknown zone {:?}", zone);
+            }
+        }
+
+        for tool in &self.tools {
+            if tool.name.is_empty() || tool.name.contains(' ') {
+                bail!("[[tool]] name must be non-empty, without spaces");
+            }
+            if tool.command.is_empty() {
+                bail!("[[tool]] {:?} command must not be empty", tool.name);
+            }
+            if 0 == tool.sandbox.concurrency {
+                bail!("[[tool]] {:?} concurrency must be at least one", tool.name);
+            }
+        }
+
+        if self.remind.max_days > MAX_REMIND_DAYS {
+            bail!("[remind] max_days must be at most {}", MAX_REMIND_DAYS);
+        }
+
+        if 0 == self.urls.max_results {
+            bail!("[urls] max_results must be at least one");
+        }
+
+        if 0 == self.output.max_lines {
+            bail!("[output] max_lines must be at least one");
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Network {
+    /// used in logs, and to tell networks apart
+    pub name: String,
+
+    #[serde(flatten)]
+    pub server: Server,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Server {
     pub hostname: String,
 
+    /// tried in turn, after `hostname`, when reconnecting
+    #[serde(default)]
+    pub alternate_hostnames: Vec<String>,
+
     #[serde(default = "default_port")]
     pub port: u16,
 
@@ -21,12 +190,170 @@ pub struct Server {
     pub nick_password: Option<String>,
 
     pub channels: Vec<String>,
+
+    /// per-channel settings, e.g. `[server.channel."#rust"]`
+    #[serde(default, rename = "channel")]
+    pub channel_settings: HashMap<String, ChannelSettings>,
+
+    #[serde(default = "enabled")]
+    pub use_tls: bool,
+    #[serde(default)]
+    pub dangerously_accept_invalid_certs: bool,
+    /// PKCS#12 (DER) bundle, for CertFP
+    pub client_cert_path: Option<String>,
+    pub client_cert_pass: Option<String>,
+
+    pub sasl: Option<Sasl>,
+    /// defaults to `nick`
+    pub sasl_username: Option<String>,
+    /// defaults to `nick_password`
+    pub sasl_password: Option<String>,
+
+    /// whose INVITEs to accept
+    #[serde(default)]
+    pub invites: Invites,
+
+    /// seconds to wait before rejoining a channel we were kicked from
+    #[serde(default = "default_rejoin_delay")]
+    pub rejoin_delay: u64,
+    /// rejoins after kicks allowed per channel per hour; zero to stay out
+    #[serde(default)]
+    pub rejoin_limit: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Sasl {
+    Plain,
+    External,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Invites {
+    #[default]
+    Never,
+    /// only from `[admin]` operators
+    Admins,
+    All,
+}
+
+impl Server {
+    pub fn validate(&self) -> Result<()> {
+        if self.hostname.is_empty() || self.alternate_hostnames.iter().any(|h| h.is_empty()) {
+            bail!("hostnames must not be empty");
+        }
+
+        if 0 == self.port {
+            bail!("port must not be zero");
+        }
+
+        if self.nick.is_empty() || self.nick.contains(' ') {
+            bail!("invalid nick: {:?}", self.nick);
+        }
+
+        if !self.use_tls {
+            if self.dangerously_accept_invalid_certs {
+                bail!("dangerously_accept_invalid_certs requires use_tls");
+            }
+            if self.client_cert_path.is_some() {
+                bail!("client_cert_path requires use_tls");
+            }
+        }
+
+        if let Some(path) = &self.client_cert_path
+            && !Path::new(path).is_file()
+        {
+            bail!("client_cert_path {:?} is not a readable file", path);
+        }
+
+        if self.client_cert_pass.is_some() && self.client_cert_path.is_none() {
+            bail!("client_cert_pass without client_cert_path");
+        }
+
+        match self.sasl {
+            Some(Sasl::Plain) if self.sasl_password.is_none() && self.nick_password.is_none() => {
+                bail!("sasl = \"plain\" requires sasl_password or nick_password")
+            }
+            Some(Sasl::External) if self.client_cert_path.is_none() => {
+                bail!("sasl = \"external\" requires client_cert_path")
+            }
+            None if self.sasl_username.is_some() || self.sasl_password.is_some() => {
+                bail!("sasl_username/sasl_password set, but sasl isn't")
+            }
+            _ => (),
+        }
+
+        Ok(())
+    }
+}
+
+impl Server {
+    pub fn settings(&self, channel: &str) -> ChannelSettings {
+        self.channel_settings
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(channel))
+            .map(|(_, settings)| settings.clone())
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChannelSettings {
+    #[serde(default = "enabled")]
+    pub titles: bool,
+
+    #[serde(default = "enabled")]
+    pub commands: bool,
+
+    /// commands (by name, not alias) not answered here, even though others are
+    #[serde(default)]
+    pub disabled_commands: Vec<String>,
+
+    /// reply with NOTICE, which other bots (and some clients) don't react to
+    #[serde(default)]
+    pub notice: bool,
+
+    /// start titles with the nick of whoever posted the link
+    #[serde(default)]
+    pub address: bool,
+
+    /// bold hostnames and red NSFW markers; dropped anyway if the channel is +c
+    #[serde(default)]
+    pub formatting: bool,
+
+    /// remember who was last around here, for `!seen`
+    #[serde(default = "enabled")]
+    pub seen: bool,
+
+    /// keep the links posted here, and their titles, for `!url`
+    #[serde(default = "enabled")]
+    pub urls: bool,
+}
+
+impl Default for ChannelSettings {
+    fn default() -> ChannelSettings {
+        ChannelSettings {
+            titles: true,
+            commands: true,
+            disabled_commands: Vec::new(),
+            notice: false,
+            address: false,
+            formatting: false,
+            seen: true,
+            urls: true,
+        }
+    }
 }
 
 fn default_port() -> u16 {
     6697
 }
 
+fn default_rejoin_delay() -> u64 {
+    30
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Keys {
     pub imgur_client_id: String,
@@ -36,3 +363,632 @@ pub struct Keys {
     pub spotify_app_secret: String,
     pub youtube_developer_key: String,
 }
+
+/// A site-specific title extraction, tried before the generic `<title>` fallback.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Rule {
+    /// matches this host, and any subdomain of it
+    pub host: String,
+    /// regex against the path (and query); captures are available to `fetch` and `template`
+    pub path: Option<String>,
+    /// url to fetch instead of the link itself, e.g. an api endpoint
+    pub fetch: Option<String>,
+
+    // exactly one of these
+    pub css: Option<String>,
+    pub meta: Option<String>,
+    pub json: Option<String>,
+    pub regex: Option<String>,
+
+    #[serde(default = "default_template")]
+    pub template: String,
+}
+
+fn default_template() -> String {
+    "{title}".to_string()
+}
+
+/// Additions to the built-in list of tracking parameters stripped from links.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Clean {
+    /// stripped from every url; a trailing `*` matches a prefix
+    #[serde(default)]
+    pub params: Vec<String>,
+
+    /// host -> params only stripped on that host and its subdomains
+    #[serde(default)]
+    pub sites: HashMap<String, Vec<String>>,
+
+    /// append the cleaned link to the title, if it is shorter by at least `min_saving` bytes
+    #[serde(default)]
+    pub show_cleaned: bool,
+
+    #[serde(default = "default_min_saving")]
+    pub min_saving: usize,
+}
+
+impl Default for Clean {
+    fn default() -> Clean {
+        Clean {
+            params: Vec::new(),
+            sites: HashMap::new(),
+            show_cleaned: false,
+            min_saving: default_min_saving(),
+        }
+    }
+}
+
+fn default_min_saving() -> usize {
+    30
+}
+
+/// Answers to CTCP queries; an empty string means the query is ignored.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Ctcp {
+    #[serde(default = "default_version")]
+    pub version: String,
+
+    #[serde(default = "default_source")]
+    pub source: String,
+
+    #[serde(default)]
+    pub userinfo: String,
+
+    #[serde(default = "enabled")]
+    pub ping: bool,
+
+    #[serde(default = "enabled")]
+    pub time: bool,
+}
+
+impl Default for Ctcp {
+    fn default() -> Ctcp {
+        Ctcp {
+            version: default_version(),
+            source: default_source(),
+            userinfo: String::new(),
+            ping: true,
+            time: true,
+        }
+    }
+}
+
+fn default_version() -> String {
+    format!("unsnap {}", env!("CARGO_PKG_VERSION"))
+}
+
+fn default_source() -> String {
+    "https://github.com/FauxFaux/unsnap".to_string()
+}
+
+/// How commands are recognised.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Commands {
+    /// what commands start with, e.g. the `!` in `!qalc`
+    #[serde(default = "default_prefix")]
+    pub prefix: String,
+
+    /// also accept `unsnap: qalc 1+1`, addressed to the bot's nick
+    #[serde(default = "enabled")]
+    pub addressed: bool,
+
+    /// seconds before someone can use the same command again, unless overridden
+    #[serde(default = "default_cooldown")]
+    pub cooldown: u64,
+
+    /// command -> seconds, replacing `cooldown` for that command
+    #[serde(default)]
+    pub cooldowns: HashMap<String, u64>,
+
+    /// extra name -> command, e.g. `c = "qalc"`
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+}
+
+impl Default for Commands {
+    fn default() -> Commands {
+        Commands {
+            prefix: default_prefix(),
+            addressed: true,
+            cooldown: default_cooldown(),
+            cooldowns: HashMap::new(),
+            aliases: HashMap::new(),
+        }
+    }
+}
+
+fn default_prefix() -> String {
+    "!".to_string()
+}
+
+fn default_cooldown() -> u64 {
+    2
+}
+
+/// What the bot does with messages sent directly to it, rather than to a channel.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Query {
+    /// commands (without the `!`) which may be used in private
+    #[serde(default = "default_query_commands")]
+    pub commands: Vec<String>,
+
+    /// title links sent in private
+    #[serde(default)]
+    pub titles: bool,
+}
+
+impl Default for Query {
+    fn default() -> Query {
+        Query {
+            commands: default_query_commands(),
+            titles: false,
+        }
+    }
+}
+
+fn default_query_commands() -> Vec<String> {
+    vec!["qalc".to_string(), "help".to_string()]
+}
+
+/// How `!qalc` is run: in sandboxed worker processes, one per person, which are killed if a
+/// calculation takes too long.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Qalc {
+    /// with it off, or qalc not installed, the built-in calculator answers instead
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+
+    /// reads expressions on stdin, one per line; line-buffered, so answers arrive promptly
+    #[serde(default = "default_qalc_command")]
+    pub command: Vec<String>,
+
+    /// at once; further calculations wait their turn
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+
+    /// wall-clock time allowed for each calculation, including a new worker starting up
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+
+    /// per calculation
+    #[serde(default = "default_cpu_secs")]
+    pub cpu_secs: u64,
+
+    /// address space allowed to each worker
+    #[serde(default = "default_memory_mb")]
+    pub memory_mb: u64,
+
+    /// people with a worker of their own; the quietest loses theirs to make room
+    #[serde(default = "default_workers")]
+    pub workers: usize,
+
+    /// workers started in advance, ready for someone new
+    #[serde(default = "default_spare")]
+    pub spare: usize,
+
+    /// seconds someone's variables and `ans` are kept after they last used them
+    #[serde(default = "default_session_secs")]
+    pub session_secs: u64,
+
+    /// calculations before a worker is replaced, forgetting its variables
+    #[serde(default = "default_max_queries")]
+    pub max_queries: usize,
+}
+
+impl Default for Qalc {
+    fn default() -> Qalc {
+        Qalc {
+            enabled: true,
+            command: default_qalc_command(),
+            concurrency: default_concurrency(),
+            timeout_ms: default_timeout_ms(),
+            cpu_secs: default_cpu_secs(),
+            memory_mb: default_memory_mb(),
+            workers: default_workers(),
+            spare: default_spare(),
+            session_secs: default_session_secs(),
+            max_queries: default_max_queries(),
+        }
+    }
+}
+
+fn default_qalc_command() -> Vec<String> {
+    vec!["stdbuf".to_string(), "-oL".to_string(), "qalc".to_string()]
+}
+
+fn default_concurrency() -> usize {
+    2
+}
+
+fn default_timeout_ms() -> u64 {
+    2000
+}
+
+fn default_cpu_secs() -> u64 {
+    2
+}
+
+fn default_memory_mb() -> u64 {
+    512
+}
+
+fn default_workers() -> usize {
+    8
+}
+
+fn default_spare() -> usize {
+    1
+}
+
+fn default_session_secs() -> u64 {
+    30 * 60
+}
+
+fn default_max_queries() -> usize {
+    200
+}
+
+/// How `!time` knows where people are.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Time {
+    /// for times given without a zone, by someone without a home
+    #[serde(default = "default_zone")]
+    pub zone: String,
+
+    /// nick or services account -> their zone, e.g. `faux = "Europe/London"`
+    #[serde(default)]
+    pub homes: BTreeMap<String, String>,
+}
+
+impl Default for Time {
+    fn default() -> Time {
+        Time {
+            zone: default_zone(),
+            homes: BTreeMap::new(),
+        }
+    }
+}
+
+fn default_zone() -> String {
+    "UTC".to_string()
+}
+
+/// What `!seen` remembers, and for how long.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Seen {
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+
+    /// days after which someone's activity is forgotten
+    #[serde(default = "default_retention_days")]
+    pub retention_days: u64,
+
+    /// what people last said, rather than only that they spoke
+    #[serde(default)]
+    pub quote: bool,
+
+    /// nicks never remembered, as if they'd said `!seen off`
+    #[serde(default)]
+    pub ignore: Vec<String>,
+}
+
+impl Default for Seen {
+    fn default() -> Seen {
+        Seen {
+            enabled: true,
+            retention_days: default_retention_days(),
+            quote: false,
+            ignore: Vec::new(),
+        }
+    }
+}
+
+fn default_retention_days() -> u64 {
+    90
+}
+
+/// How `!tell` messages are kept and passed on.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Tell {
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+
+    /// messages someone may have waiting at once
+    #[serde(default = "default_max_per_sender")]
+    pub max_per_sender: usize,
+
+    /// days after which undelivered messages are dropped
+    #[serde(default = "default_expiry_days")]
+    pub expiry_days: u64,
+
+    #[serde(default)]
+    pub deliver: Delivery,
+}
+
+impl Default for Tell {
+    fn default() -> Tell {
+        Tell {
+            enabled: true,
+            max_per_sender: default_max_per_sender(),
+            expiry_days: default_expiry_days(),
+            deliver: Delivery::default(),
+        }
+    }
+}
+
+fn default_max_per_sender() -> usize {
+    5
+}
+
+fn default_expiry_days() -> u64 {
+    30
+}
+
+/// Where `!tell` messages are passed on.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Delivery {
+    /// where they spoke or joined, unless the bot is muted there
+    #[default]
+    Channel,
+    Private,
+}
+
+/// How `!remind` reminders are kept.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Remind {
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+
+    /// reminders someone may have waiting at once
+    #[serde(default = "default_max_per_person")]
+    pub max_per_person: usize,
+
+    /// how far ahead reminders may be set
+    #[serde(default = "default_max_days")]
+    pub max_days: u64,
+}
+
+impl Default for Remind {
+    fn default() -> Remind {
+        Remind {
+            enabled: true,
+            max_per_person: default_max_per_person(),
+            max_days: default_max_days(),
+        }
+    }
+}
+
+fn default_max_per_person() -> usize {
+    10
+}
+
+/// A century; far enough ahead for anyone, and nowhere near overflowing.
+const MAX_REMIND_DAYS: u64 = 36_500;
+
+fn default_max_days() -> u64 {
+    365
+}
+
+/// The log of titled links, searched by `!url`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Urls {
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+
+    /// days after which links are forgotten
+    #[serde(default = "default_url_retention_days")]
+    pub retention_days: u64,
+
+    /// links shown for a search, or `!url last`
+    #[serde(default = "default_max_results")]
+    pub max_results: usize,
+}
+
+impl Default for Urls {
+    fn default() -> Urls {
+        Urls {
+            enabled: true,
+            retention_days: default_url_retention_days(),
+            max_results: default_max_results(),
+        }
+    }
+}
+
+fn default_url_retention_days() -> u64 {
+    365
+}
+
+fn default_max_results() -> usize {
+    3
+}
+
+/// An external program, run in a sandbox for each use of its command.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Tool {
+    /// the command, e.g. `units` for `!units`
+    pub name: String,
+
+    /// the program, and any arguments which come before the user's
+    pub command: Vec<String>,
+
+    #[serde(default)]
+    pub input: ToolInput,
+
+    #[serde(default)]
+    pub aliases: Vec<String>,
+
+    /// what goes after the name, for `!help`
+    #[serde(default)]
+    pub usage: String,
+
+    #[serde(default)]
+    pub help: String,
+
+    /// words of arguments needed; with fewer, the usage is shown instead
+    #[serde(default)]
+    pub min_args: usize,
+
+    /// lets arguments start with `-`, which most programs would take as options
+    #[serde(default)]
+    pub allow_options: bool,
+
+    /// keeps the output's lines apart, for `[output] max_lines`, instead of joining them
+    #[serde(default)]
+    pub multiline: bool,
+
+    #[serde(default)]
+    pub sandbox: Sandbox,
+}
+
+/// How the user's text reaches a tool.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ToolInput {
+    /// all of it, as the last argument
+    #[default]
+    Argument,
+    /// split at spaces, except inside double quotes, as the last arguments
+    Words,
+    /// on its stdin, with no extra arguments
+    Stdin,
+}
+
+/// What a tool may use, each time it's run.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Sandbox {
+    /// runs at once; further uses wait their turn
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+
+    /// wall-clock time, after which it's killed
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+
+    #[serde(default = "default_cpu_secs")]
+    pub cpu_secs: u64,
+
+    /// address space
+    #[serde(default = "default_tool_memory_mb")]
+    pub memory_mb: u64,
+
+    /// the largest file it may write
+    #[serde(default = "default_file_size_kb")]
+    pub file_size_kb: u64,
+
+    /// lets it open sockets at all, e.g. for `dig` to ask a local resolver
+    #[serde(default)]
+    pub network: bool,
+}
+
+impl Default for Sandbox {
+    fn default() -> Sandbox {
+        Sandbox {
+            concurrency: default_concurrency(),
+            timeout_ms: default_timeout_ms(),
+            cpu_secs: default_cpu_secs(),
+            memory_mb: default_tool_memory_mb(),
+            file_size_kb: default_file_size_kb(),
+            network: false,
+        }
+    }
+}
+
+fn default_tool_memory_mb() -> u64 {
+    256
+}
+
+fn default_file_size_kb() -> u64 {
+    1024
+}
+
+/// How replies which don't fit on one line are shown.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Output {
+    /// lines a single reply may use; beyond this, it's cut short with an ellipsis
+    #[serde(default = "default_max_lines")]
+    pub max_lines: usize,
+}
+
+impl Default for Output {
+    fn default() -> Output {
+        Output {
+            max_lines: default_max_lines(),
+        }
+    }
+}
+
+fn default_max_lines() -> usize {
+    1
+}
+
+/// Who may control the bot, by private message.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Admin {
+    /// services accounts, which the network reports when it supports account-tag
+    #[serde(default)]
+    pub accounts: Vec<String>,
+
+    /// `nick!user@host` patterns, with `*` and `?`; prefer accounts or cloaks
+    #[serde(default)]
+    pub hostmasks: Vec<String>,
+
+    /// file to append a line to for every admin command, as well as logging it
+    pub audit_log: Option<PathBuf>,
+}
+
+fn enabled() -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+
+    #[test]
+    fn example() {
+        let config: Config = toml::from_str(include_str!("../bot.toml.example")).unwrap();
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn networks() {
+        let config: Config = toml::from_str(
+            r##"
+            [[network]]
+            name = "libera"
+            hostname = "irc.libera.chat"
+            nick = "unsnap"
+            channels = ["#unsnap"]
+            sasl = "plain"
+            nick_password = "hunter2"
+
+            [network.channel."#quiet"]
+            titles = false
+
+            [[network]]
+            name = "oftc"
+            hostname = "irc.oftc.net"
+            port = 6697
+            nick = "unsnap"
+            channels = []
+
+            [keys]
+            imgur_client_id = ""
+            twitter_app_key = ""
+            twitter_app_secret = ""
+            spotify_app_key = ""
+            spotify_app_secret = ""
+            youtube_developer_key = ""
+            "##,
+        )
+        .unwrap();
+        config.validate().unwrap();
+
+        let networks = config.all_networks();
+        assert_eq!(2, networks.len());
+        assert_eq!("oftc", networks[1].name);
+        assert!(!networks[0].server.settings("#QUIET").titles);
+        assert!(networks[0].server.settings("#unsnap").titles);
+    }
+}
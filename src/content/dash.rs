@@ -14,10 +14,10 @@ pub fn highest_stream(playlist: &str) -> Result<String, &'static str> {
                     curr_bandwidth = e.attributes().find_map(|a| a.ok().and_then(bandwidth));
                 }
                 b"BaseURL" => {
-                    if let Some(curr) = curr_bandwidth {
-                        if best.as_ref().map(|(_name, band)| *band).unwrap_or(0) < curr {
-                            collect_text = true;
-                        }
+                    if let Some(curr) = curr_bandwidth
+                        && best.as_ref().map(|(_name, band)| *band).unwrap_or(0) < curr
+                    {
+                        collect_text = true;
                     }
                 }
                 _ => (),
@@ -0,0 +1,116 @@
+use crate::config;
+
+const DELIMITER: char = '\x01';
+
+#[derive(Debug, PartialEq)]
+pub enum Ctcp<'a> {
+    /// `/me does something`
+    Action(&'a str),
+    /// anything else, e.g. `VERSION` or `PING 1234`: (command, arguments)
+    Query(&'a str, &'a str),
+}
+
+/// `None` if the message is plain text.
+pub fn parse(text: &str) -> Option<Ctcp<'_>> {
+    let body = text.strip_prefix(DELIMITER)?;
+    // some clients forget the closing delimiter
+    let body = body.strip_suffix(DELIMITER).unwrap_or(body);
+
+    let (command, args) = body.split_once(' ').unwrap_or((body, ""));
+
+    Some(if command.eq_ignore_ascii_case("ACTION") {
+        Ctcp::Action(args)
+    } else {
+        Ctcp::Query(command, args)
+    })
+}
+
+/// The body of the reply to a query, if we're configured to answer it.
+pub fn reply(config: &config::Ctcp, command: &str, args: &str) -> Option<String> {
+    let answer = |value: &str| {
+        if value.is_empty() {
+            None
+        } else {
+            Some(format!("{} {}", command.to_ascii_uppercase(), value))
+        }
+    };
+
+    match command.to_ascii_uppercase().as_str() {
+        "VERSION" => answer(&config.version),
+        "SOURCE" => answer(&config.source),
+        "USERINFO" => answer(&config.userinfo),
+        "PING" if config.ping => answer(args),
+        "TIME" if config.time => answer(&chrono::Local::now().to_rfc2822()),
+        "CLIENTINFO" => answer(&supported(config).join(" ")),
+        _ => None,
+    }
+}
+
+fn supported(config: &config::Ctcp) -> Vec<&'static str> {
+    let mut supported = vec!["ACTION", "CLIENTINFO"];
+    for (name, enabled) in [
+        ("PING", config.ping),
+        ("SOURCE", !config.source.is_empty()),
+        ("TIME", config.time),
+        ("USERINFO", !config.userinfo.is_empty()),
+        ("VERSION", !config.version.is_empty()),
+    ] {
+        if enabled {
+            supported.push(name);
+        }
+    }
+    supported
+}
+
+/// Wraps a reply body for sending in a NOTICE.
+pub fn quote(body: &str) -> String {
+    format!("{}{}{}", DELIMITER, body, DELIMITER)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Ctcp;
+    use super::parse;
+    use super::reply;
+    use crate::config;
+
+    #[test]
+    fn parsing() {
+        assert_eq!(None, parse("hello https://example.com/"));
+        assert_eq!(
+            Some(Ctcp::Action("shares https://example.com/")),
+            parse("\x01ACTION shares https://example.com/\x01")
+        );
+        assert_eq!(Some(Ctcp::Action("waves")), parse("\x01action waves"));
+        assert_eq!(Some(Ctcp::Query("VERSION", "")), parse("\x01VERSION\x01"));
+        assert_eq!(
+            Some(Ctcp::Query("PING", "1234 5678")),
+            parse("\x01PING 1234 5678\x01")
+        );
+    }
+
+    #[test]
+    fn replies() {
+        let config = config::Ctcp {
+            userinfo: String::new(),
+            time: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            Some(config.version.to_string()),
+            reply(&config, "version", "").map(|r| r["VERSION ".len()..].to_string())
+        );
+        assert_eq!(
+            Some("PING 1234".to_string()),
+            reply(&config, "PING", "1234")
+        );
+        assert_eq!(None, reply(&config, "PING", ""));
+        assert_eq!(None, reply(&config, "TIME", ""));
+        assert_eq!(None, reply(&config, "USERINFO", ""));
+        assert_eq!(None, reply(&config, "FINGER", ""));
+        assert_eq!(
+            Some("CLIENTINFO ACTION CLIENTINFO PING SOURCE VERSION".to_string()),
+            reply(&config, "CLIENTINFO", "")
+        );
+    }
+}
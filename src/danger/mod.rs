@@ -1,3 +1,6 @@
 mod qalc;
+pub mod sandbox;
+mod tools;
 
-pub use self::qalc::qalc;
+pub use self::qalc::Qalc;
+pub use self::tools::Tools;
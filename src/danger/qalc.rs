@@ -1,46 +1,398 @@
-use std::ffi::OsString;
-use std::io::Write;
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
 use std::time::Duration;
+use std::time::Instant;
 
+use anyhow::Context as _;
 use anyhow::Result;
-use anyhow::anyhow;
 use anyhow::bail;
-use subprocess::Popen;
-use subprocess::PopenConfig;
-use subprocess::Redirection;
-use tempfile::NamedTempFile;
-
-use crate::titles::cleanup_newlines;
-
-pub fn qalc(input: &str) -> Result<String> {
-    let mut temp = NamedTempFile::new()?;
-
-    let input = input.replace(" ;; ", "\n");
-
-    writeln!(&mut temp, "{}", input)?;
-    let path = temp.into_temp_path();
-
-    let mut child = Popen::create(
-        // TODO: very ugly coercing here
-        &[
-            OsString::from("qalc").as_os_str(),
-            OsString::from("-f").as_os_str(),
-            path.as_os_str(),
-        ],
-        PopenConfig {
-            stdout: Redirection::Pipe,
-            stderr: Redirection::Merge,
-            ..Default::default()
-        },
-    )?;
-
-    if let Some(_exit) = child.wait_timeout(Duration::from_secs(1))? {
-        let (output, _) = child.communicate(None)?;
-        Ok(cleanup_newlines(
-            &output.ok_or_else(|| anyhow!("output requested"))?,
-        ))
-    } else {
-        child.kill()?;
-        bail!("timeout, kill attempted");
+use anyhow::format_err;
+use tempfile::TempDir;
+use tokio::io::AsyncBufReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::io::BufReader;
+use tokio::io::Lines;
+use tokio::process::Child;
+use tokio::process::ChildStdin;
+use tokio::process::ChildStdout;
+use tokio::sync::Semaphore;
+
+use super::sandbox;
+use super::sandbox::Limits;
+use crate::config;
+
+/// Runs calculations in long-lived, sandboxed `qalc` processes, one per person, so they keep
+/// `ans` and their variables between calls.
+pub struct Qalc {
+    config: config::Qalc,
+    running: Semaphore,
+    limits: Limits,
+    /// (network, lower-cased nick) -> their session
+    sessions: Mutex<HashMap<(String, String), Session>>,
+    /// started in advance, so new sessions don't wait for qalc to load
+    spare: Mutex<Vec<Worker>>,
+    /// it isn't installed, or its answers can't be told apart, so there's no point trying again
+    missing: AtomicBool,
+}
+
+struct Session {
+    /// `None` until they first calculate something, or after their worker was discarded
+    worker: Arc<tokio::sync::Mutex<Option<Worker>>>,
+    last_used: Instant,
+}
+
+/// qalc's own commands, rather than calculations: they change its settings, which the end of
+/// an answer is found with, save files, or stop it.
+const COMMANDS: &[&str] = &[
+    "approximate",
+    "assume",
+    "base",
+    "clear",
+    "copy",
+    "delete",
+    "exact",
+    "exit",
+    "exrates",
+    "find",
+    "function",
+    "help",
+    "info",
+    "keep",
+    "list",
+    "load",
+    "m+",
+    "m-",
+    "mc",
+    "mode",
+    "mr",
+    "ms",
+    "pop",
+    "quit",
+    "rotate",
+    "rpn",
+    "save",
+    "set",
+    "stack",
+    "store",
+    "swap",
+    "unset",
+    "variable",
+];
+
+/// A running qalc, reading expressions from its stdin.
+struct Worker {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+    /// calculations it has done
+    used: usize,
+    /// the end of the last answer, whose stragglers are ignored
+    marker: Option<String>,
+    /// its own `HOME`, so anything it saves isn't seen by anyone else's
+    _home: TempDir,
+}
+
+impl Qalc {
+    pub fn new(config: &config::Qalc) -> Qalc {
+        Qalc {
+            running: Semaphore::new(config.concurrency),
+            limits: Limits {
+                timeout: Duration::from_millis(config.timeout_ms),
+                // for the worker's whole life; the timeout stops any one calculation hogging it
+                cpu_secs: config.cpu_secs * (config.max_queries as u64 + 1),
+                memory: config.memory_mb * 1024 * 1024,
+                file_size: 1024 * 1024,
+                network: false,
+            },
+            config: config.clone(),
+            sessions: Mutex::default(),
+            spare: Mutex::default(),
+            missing: AtomicBool::new(false),
+        }
+    }
+
+    /// The answer, or `None` if qalc is disabled, can't be run at all, or isn't understood.
+    pub async fn run(&self, network: &str, nick: &str, input: &str) -> Result<Option<String>> {
+        if !self.config.enabled || self.missing.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+        if input.split(" ;; ").any(is_command) {
+            return Ok(Some("Only calculations, not qalc's commands.".to_string()));
+        }
+
+        let _permit = self.running.acquire().await?;
+        let session = self.session(network, nick);
+        let mut worker = session.lock().await;
+
+        if worker
+            .as_ref()
+            .is_some_and(|worker| worker.used >= self.config.max_queries)
+        {
+            *worker = None;
+        }
+        if worker.is_none() {
+            match self.worker() {
+                Ok(started) => *worker = Some(started),
+                Err(e) if is_missing(&e) => return Ok(self.missing(e)),
+                Err(e) => return Err(e),
+            }
+        }
+
+        let started = worker.as_mut().expect("just started");
+        if started.used == 0
+            && let Err(e) = started.check(self.limits.timeout).await
+        {
+            *worker = None;
+            return Ok(self.unusable(e));
+        }
+
+        let answer = worker
+            .as_mut()
+            .expect("just started")
+            .ask(input, self.limits.timeout)
+            .await;
+        if let Err(e) = answer {
+            let exited = worker.as_mut().and_then(Worker::exit_code);
+            // whatever it's in the middle of, it can't be trusted with the next question
+            *worker = None;
+            // what shells, env and stdbuf exit with when they can't run the command
+            if let Some(126 | 127) = exited {
+                return Ok(self.missing(e));
+            }
+            return Err(e);
+        }
+        answer.map(Some)
+    }
+
+    fn missing(&self, e: anyhow::Error) -> Option<String> {
+        if !self.missing.swap(true, Ordering::Relaxed) {
+            warn!("qalc can't be run, using the built-in calculator: {:?}", e);
+        }
+        None
+    }
+
+    fn unusable(&self, e: anyhow::Error) -> Option<String> {
+        if !self.missing.swap(true, Ordering::Relaxed) {
+            error!(
+                "qalc isn't understood, using the built-in calculator: {:?}",
+                e
+            );
+        }
+        None
+    }
+
+    fn session(&self, network: &str, nick: &str) -> Arc<tokio::sync::Mutex<Option<Worker>>> {
+        let mut sessions = self.sessions.lock().expect("poisoned");
+        let idle = Duration::from_secs(self.config.session_secs);
+        sessions.retain(|_, session| session.last_used.elapsed() < idle);
+
+        let key = (network.to_string(), nick.to_lowercase());
+        if !sessions.contains_key(&key)
+            && sessions.len() >= self.config.workers
+            && let Some(quietest) = sessions
+                .iter()
+                .min_by_key(|(_, session)| session.last_used)
+                .map(|(key, _)| key.clone())
+        {
+            sessions.remove(&quietest);
+        }
+
+        let session = sessions.entry(key).or_insert_with(|| Session {
+            worker: Arc::default(),
+            last_used: Instant::now(),
+        });
+        session.last_used = Instant::now();
+        Arc::clone(&session.worker)
+    }
+
+    /// A spare worker, if there's a live one, and tops up the spares.
+    fn worker(&self) -> Result<Worker> {
+        let mut spare = self.spare.lock().expect("poisoned");
+        let worker = loop {
+            match spare.pop() {
+                Some(mut worker) => {
+                    if worker.alive() {
+                        break worker;
+                    }
+                }
+                None => break Worker::start(&self.config.command, &self.limits)?,
+            }
+        };
+
+        while spare.len() < self.config.spare {
+            match Worker::start(&self.config.command, &self.limits) {
+                Ok(worker) => spare.push(worker),
+                Err(e) => {
+                    warn!("starting a spare qalc: {:?}", e);
+                    break;
+                }
+            }
+        }
+
+        Ok(worker)
+    }
+}
+
+impl Worker {
+    fn start(command: &[String], limits: &Limits) -> Result<Worker> {
+        let (program, args) = command
+            .split_first()
+            .ok_or_else(|| format_err!("no qalc command"))?;
+        let home = sandbox::home()?;
+        let mut child = sandbox::spawn(program, args, home.path(), limits)?;
+        let stdin = child.stdin.take().expect("piped");
+        let stdout = BufReader::new(child.stdout.take().expect("piped")).lines();
+
+        let mut stderr = BufReader::new(child.stderr.take().expect("piped")).lines();
+        tokio::spawn(async move {
+            while let Ok(Some(line)) = stderr.next_line().await {
+                warn!("qalc: {}", line);
+            }
+        });
+
+        Ok(Worker {
+            child,
+            stdin,
+            stdout,
+            used: 0,
+            marker: None,
+            _home: home,
+        })
+    }
+
+    fn alive(&mut self) -> bool {
+        matches!(self.child.try_wait(), Ok(None))
+    }
+
+    fn exit_code(&mut self) -> Option<i32> {
+        self.child.try_wait().ok().flatten()?.code()
+    }
+
+    /// Whether it complains about the marker the way `ask` expects; the wording isn't
+    /// fixed, so a qalc which says something else would otherwise time out every answer.
+    async fn check(&mut self, timeout: Duration) -> Result<()> {
+        self.send(String::new(), timeout)
+            .await
+            .context("no end marker seen")?;
+        Ok(())
+    }
+
+    async fn ask(&mut self, input: &str, timeout: Duration) -> Result<String> {
+        self.used += 1;
+        let request = input
+            .split(" ;; ")
+            .map(|expression| format!("{}\n", expression.trim()))
+            .collect();
+        self.send(request, timeout).await
+    }
+
+    async fn send(&mut self, mut request: String, timeout: Duration) -> Result<String> {
+        // a name qalc doesn't know, which it complains about by name whatever the number
+        // formatting, marks the end of the real answer
+        let marker: String = "unsnapend"
+            .chars()
+            .chain(std::iter::repeat_with(fastrand::lowercase).take(12))
+            .collect();
+        request.push_str(&marker);
+        request.push('\n');
+        let previous = self.marker.replace(marker.clone());
+
+        let answer = async {
+            self.stdin.write_all(request.as_bytes()).await?;
+            self.stdin.flush().await?;
+
+            let mut answer = Vec::new();
+            loop {
+                let Some(line) = self.stdout.next_line().await? else {
+                    let status = self.child.wait().await?;
+                    bail!("qalc exited: {}", status);
+                };
+                if line.contains(&marker) {
+                    return Ok(answer.join("\n"));
+                }
+                let straggler = previous
+                    .as_ref()
+                    .is_some_and(|previous| line.contains(previous));
+                if !straggler && !line.trim().is_empty() {
+                    answer.push(line);
+                }
+            }
+        };
+
+        tokio::time::timeout(timeout, answer)
+            .await
+            .map_err(|_| format_err!("timed out after {:?}", timeout))?
+    }
+}
+
+fn is_command(expression: &str) -> bool {
+    let word = expression.trim().trim_start_matches('/');
+    let word = word.split_whitespace().next().unwrap_or("");
+    COMMANDS.contains(&word.to_lowercase().as_str())
+}
+
+fn is_missing(e: &anyhow::Error) -> bool {
+    e.chain()
+        .filter_map(|cause| cause.downcast_ref::<io::Error>())
+        .any(|e| e.kind() == io::ErrorKind::NotFound)
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        sandbox::kill(&self.child);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Qalc;
+    use crate::config;
+
+    #[tokio::test]
+    async fn sessions() {
+        // like qalc, complains about the unknown marker, and then answers it anyway
+        let script = r#"n=0; while read -r line; do case $line in
+            unsnapend*) echo "error: \"$line\" is not a valid variable."; echo "$line = 0";;
+            *) n=$((n+1)); echo "$line = $n";;
+        esac; done"#;
+        let qalc = Qalc::new(&config::Qalc {
+            command: vec!["sh".to_string(), "-c".to_string(), script.to_string()],
+            max_queries: 2,
+            ..config::Qalc::default()
+        });
+
+        let run = async |nick, input| qalc.run("net", nick, input).await.unwrap().unwrap();
+        assert_eq!("a = 1\nb = 2", run("faux", "a ;; b").await);
+        assert_eq!("c = 3", run("Faux", "c").await);
+        assert_eq!("d = 1", run("other", "d").await);
+        // recycled, after max_queries
+        assert_eq!("e = 1", run("faux", "e").await);
+        assert_eq!(
+            "Only calculations, not qalc's commands.",
+            run("faux", "1 ;; /SAVE definitions").await
+        );
+
+        let missing = Qalc::new(&config::Qalc {
+            command: vec!["/nonexistent/qalc".to_string()],
+            ..config::Qalc::default()
+        });
+        assert_eq!(None, missing.run("net", "faux", "1").await.unwrap());
+
+        // answers, but never mentions the marker
+        let mute = Qalc::new(&config::Qalc {
+            command: vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                "cat >/dev/null".to_string(),
+            ],
+            timeout_ms: 100,
+            ..config::Qalc::default()
+        });
+        assert_eq!(None, mute.run("net", "faux", "1").await.unwrap());
+        assert_eq!(None, mute.run("net", "faux", "1").await.unwrap());
     }
 }
@@ -0,0 +1,596 @@
+use std::ffi::CString;
+use std::ffi::OsStr;
+use std::io;
+use std::path::Path;
+use std::process::ExitStatus;
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::Context as _;
+use anyhow::Result;
+use anyhow::format_err;
+use tempfile::TempDir;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Child;
+use tokio::process::Command;
+
+/// What a sandboxed process may use.
+#[derive(Clone, Debug)]
+pub struct Limits {
+    /// wall-clock time for each piece of work, after which it's killed
+    pub timeout: Duration,
+    pub cpu_secs: u64,
+    /// address space, in bytes
+    pub memory: u64,
+    /// the largest file it may write, in bytes
+    pub file_size: u64,
+    /// whether it may open sockets, e.g. to ask a local resolver
+    pub network: bool,
+}
+
+/// How a sandboxed process ended.
+#[derive(Debug)]
+pub struct Finished {
+    pub status: ExitStatus,
+    /// stdout, then stderr, each cut short at `MAX_OUTPUT`
+    pub output: String,
+}
+
+/// More than this, from either stream, is thrown away.
+const MAX_OUTPUT: u64 = 64 * 1024;
+
+/// A fresh, empty home directory for a sandboxed program; removed when it's dropped.
+pub fn home() -> Result<TempDir> {
+    tempfile::Builder::new()
+        .prefix("unsnap-sandbox-")
+        .tempdir()
+        .context("creating a sandbox home")
+}
+
+/// Starts a program with rlimits, without network access (unless allowed), with dangerous
+/// syscalls refused, and with `home` as its `HOME` and working directory.
+///
+/// Where the kernel has landlock, it can only read and run what's in the system directories
+/// (`/usr`, `/etc`, ...), and only change `home`; the bot's configuration and database are out of
+/// reach, unless they're kept in one of those. Without landlock (before Linux 5.13), it can read
+/// and write whatever the bot can. Either way, it can see other processes in `/proc`, though not
+/// their memory, where landlock is there.
+///
+/// Its stdin, stdout and stderr are piped; it's killed if the `Child` is dropped.
+pub fn spawn<S: AsRef<OsStr>>(
+    program: &str,
+    args: &[S],
+    home: &Path,
+    limits: &Limits,
+) -> Result<Child> {
+    let confined = limits.clone();
+    // built out here, as allocating after fork isn't safe
+    let filter = seccomp::filter(limits.network);
+    let rules = landlock::rules(home);
+    let ids = IdMaps::new();
+
+    let mut command = Command::new(program);
+    command
+        .args(args)
+        .env_clear()
+        .envs(
+            ["PATH", "LANG"]
+                .iter()
+                .filter_map(|name| Some((name, std::env::var_os(name)?))),
+        )
+        .env("HOME", home)
+        .env("TMPDIR", home)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        // not wherever the bot is, next to its configuration
+        .current_dir(home)
+        // so it can be killed along with its children
+        .process_group(0)
+        .kill_on_drop(true);
+
+    // SAFETY: `confine` only makes syscalls, which is all that's allowed between fork and exec
+    unsafe {
+        command.pre_exec(move || confine(&confined, filter.as_deref(), rules.as_ref(), &ids));
+    }
+
+    command
+        .spawn()
+        .with_context(|| format_err!("starting {:?}", program))
+}
+
+/// Runs a program to completion, with the input on its stdin.
+///
+/// It's killed, along with anything it started, if it takes longer than `limits.timeout`.
+pub async fn run<S: AsRef<OsStr>>(
+    program: &str,
+    args: &[S],
+    input: &str,
+    limits: &Limits,
+) -> Result<Finished> {
+    let home = home()?;
+    let mut child = spawn(program, args, home.path(), limits)?;
+    let mut stdin = child.stdin.take().expect("piped");
+    let mut stdout = child.stdout.take().expect("piped");
+    let mut stderr = child.stderr.take().expect("piped");
+
+    let finished = tokio::time::timeout(limits.timeout, async {
+        let write = async {
+            // it may well exit without reading it all, which is its business
+            let _ = stdin.write_all(input.as_bytes()).await;
+            drop(stdin);
+            Ok(())
+        };
+        let ((), stdout, stderr) = tokio::try_join!(write, read(&mut stdout), read(&mut stderr))?;
+        let status = child.wait().await?;
+        Ok::<_, io::Error>((status, stdout + stderr.as_str()))
+    })
+    .await;
+
+    match finished {
+        Ok(finished) => {
+            let (status, output) =
+                finished.with_context(|| format_err!("running {:?}", program))?;
+            Ok(Finished { status, output })
+        }
+        Err(_) => {
+            kill(&child);
+            // waits, so nothing is left behind as a zombie
+            child.kill().await?;
+            Err(format_err!(
+                "{:?} timed out after {:?}",
+                program,
+                limits.timeout
+            ))
+        }
+    }
+}
+
+async fn read(stream: &mut (impl AsyncRead + Unpin)) -> io::Result<String> {
+    let mut bytes = Vec::new();
+    (&mut *stream)
+        .take(MAX_OUTPUT)
+        .read_to_end(&mut bytes)
+        .await?;
+    // drain the rest, so it doesn't block writing
+    tokio::io::copy(stream, &mut tokio::io::sink()).await?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Kills the child, and anything it has started; tokio reaps it.
+pub fn kill(child: &Child) {
+    if let Some(pid) = child.id() {
+        // SAFETY: it's our child, and its group, which it can't have left
+        unsafe { libc::kill(-(pid as libc::pid_t), libc::SIGKILL) };
+    }
+}
+
+/// Our own ids, mapped to themselves in a new user namespace, so files it creates have an owner.
+struct IdMaps {
+    uid: CString,
+    gid: CString,
+}
+
+impl IdMaps {
+    fn new() -> IdMaps {
+        // SAFETY: these can't fail
+        let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+        let map = |id| CString::new(format!("{} {} 1", id, id)).expect("digits");
+        IdMaps {
+            uid: map(uid),
+            gid: map(gid),
+        }
+    }
+
+    /// In the child; the kernel insists setgroups is refused before gids are mapped.
+    fn write(&self) -> io::Result<()> {
+        for (path, content) in [
+            (c"/proc/self/setgroups", c"deny"),
+            (c"/proc/self/uid_map", self.uid.as_c_str()),
+            (c"/proc/self/gid_map", self.gid.as_c_str()),
+        ] {
+            // SAFETY: the strings are nul-terminated, and the fd is ours
+            let written = unsafe {
+                let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+                if fd < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                let bytes = content.to_bytes();
+                let written = libc::write(fd, bytes.as_ptr().cast(), bytes.len());
+                libc::close(fd);
+                written
+            };
+            if written < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs in the child, between fork and exec.
+fn confine(
+    limits: &Limits,
+    filter: Option<&[libc::sock_filter]>,
+    rules: Option<&landlock::Rules>,
+    ids: &IdMaps,
+) -> io::Result<()> {
+    for (resource, value) in [
+        (libc::RLIMIT_CPU, limits.cpu_secs),
+        (libc::RLIMIT_AS, limits.memory),
+        (libc::RLIMIT_FSIZE, limits.file_size),
+        (libc::RLIMIT_CORE, 0),
+    ] {
+        let limit = libc::rlimit {
+            rlim_cur: value,
+            rlim_max: value,
+        };
+        if 0 != unsafe { libc::setrlimit(resource, &limit) } {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    // a user namespace lets us have an empty network namespace without being root
+    let isolated = limits.network
+        || if 0 == unsafe { libc::unshare(libc::CLONE_NEWUSER | libc::CLONE_NEWNET) } {
+            // unmapped, it can't create files, even in its home; it can manage without
+            let _ = ids.write();
+            true
+        } else {
+            0 == unsafe { libc::unshare(libc::CLONE_NEWNET) }
+        };
+
+    if 0 != unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } {
+        return Err(io::Error::last_os_error());
+    }
+
+    if let Some(rules) = rules {
+        landlock::restrict(rules)?;
+    }
+
+    let filtered = match filter {
+        Some(filter) => {
+            let program = libc::sock_fprog {
+                len: filter.len() as u16,
+                filter: filter.as_ptr() as *mut libc::sock_filter,
+            };
+            0 == unsafe {
+                libc::prctl(
+                    libc::PR_SET_SECCOMP,
+                    libc::SECCOMP_MODE_FILTER,
+                    &program as *const libc::sock_fprog,
+                )
+            }
+        }
+        None => false,
+    };
+
+    // either stops it opening sockets; with neither, it's not going to run
+    if !limits.network && !isolated && !filtered {
+        return Err(io::Error::from_raw_os_error(libc::EPERM));
+    }
+
+    Ok(())
+}
+
+mod landlock {
+    use std::ffi::CString;
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+    use std::sync::OnceLock;
+
+    /// Where programs, their libraries and data, and devices live: readable, and runnable.
+    const SYSTEM: &[&str] = &[
+        "/usr", "/bin", "/sbin", "/lib", "/lib32", "/lib64", "/etc", "/opt", "/nix", "/dev",
+        "/proc", "/sys",
+    ];
+
+    /// `LANDLOCK_ACCESS_FS_*`; the rest of the handled bits are ways of changing things.
+    const EXECUTE: u64 = 1 << 0;
+    const WRITE_FILE: u64 = 1 << 1;
+    const READ_FILE: u64 = 1 << 2;
+    const READ_DIR: u64 = 1 << 3;
+    const TRUNCATE: u64 = 1 << 14;
+
+    const CREATE_RULESET_VERSION: libc::c_uint = 1;
+    const RULE_PATH_BENEATH: libc::c_uint = 1;
+
+    #[repr(C)]
+    struct RulesetAttr {
+        handled_access_fs: u64,
+    }
+
+    #[repr(C, packed)]
+    struct PathBeneath {
+        allowed_access: u64,
+        parent_fd: i32,
+    }
+
+    /// What may be done where, ready to apply after fork.
+    pub struct Rules {
+        handled: u64,
+        paths: Vec<(CString, u64)>,
+    }
+
+    /// The kernel's landlock version; zero, with a warning, the first time, if it hasn't any.
+    fn abi() -> i64 {
+        static ABI: OnceLock<i64> = OnceLock::new();
+        *ABI.get_or_init(|| {
+            // SAFETY: asks for the version, which touches no memory
+            let abi = unsafe {
+                libc::syscall(
+                    libc::SYS_landlock_create_ruleset,
+                    std::ptr::null::<RulesetAttr>(),
+                    0,
+                    CREATE_RULESET_VERSION,
+                )
+            };
+            if abi <= 0 {
+                warn!("no landlock: sandboxed programs can read and write what the bot can");
+            }
+            abi.max(0)
+        })
+    }
+
+    /// Reading the system directories, and anything in `home`; `None` without landlock.
+    pub fn rules(home: &Path) -> Option<Rules> {
+        // every kind of access the kernel knows about, so none is left unchecked
+        let handled = match abi() {
+            0 => return None,
+            1 => (1 << 13) - 1,
+            2 => (1 << 14) - 1,
+            3 | 4 => (1 << 15) - 1,
+            _ => (1 << 16) - 1,
+        };
+        let mut paths = SYSTEM
+            .iter()
+            .map(|path| {
+                let path = CString::new(*path).expect("no nul");
+                (path, EXECUTE | READ_FILE | READ_DIR)
+            })
+            .collect::<Vec<_>>();
+        paths.push((
+            c"/dev/null".to_owned(),
+            (READ_FILE | WRITE_FILE | TRUNCATE) & handled,
+        ));
+        paths.push((CString::new(home.as_os_str().as_bytes()).ok()?, handled));
+        Some(Rules { handled, paths })
+    }
+
+    /// In the child: only syscalls.
+    pub fn restrict(rules: &Rules) -> io::Result<()> {
+        let attr = RulesetAttr {
+            handled_access_fs: rules.handled,
+        };
+        // SAFETY: the attr is what the kernel expects, and lives through the call
+        let ruleset = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_create_ruleset,
+                &attr as *const RulesetAttr,
+                size_of::<RulesetAttr>(),
+                0,
+            )
+        };
+        if ruleset < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let ruleset = ruleset as libc::c_int;
+
+        let restricted = (|| {
+            for (path, access) in &rules.paths {
+                // SAFETY: nul-terminated
+                let fd = unsafe { libc::open(path.as_ptr(), libc::O_PATH | libc::O_CLOEXEC) };
+                if fd < 0 {
+                    // not everywhere has a /lib32, or a /nix
+                    continue;
+                }
+                let rule = PathBeneath {
+                    allowed_access: *access,
+                    parent_fd: fd,
+                };
+                // SAFETY: the rule lives through the call, and the fds are ours
+                let added = unsafe {
+                    let added = libc::syscall(
+                        libc::SYS_landlock_add_rule,
+                        ruleset,
+                        RULE_PATH_BENEATH,
+                        &rule as *const PathBeneath,
+                        0,
+                    );
+                    libc::close(fd);
+                    added
+                };
+                if 0 != added {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            // SAFETY: it's our ruleset
+            match unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset, 0) } {
+                0 => Ok(()),
+                _ => Err(io::Error::last_os_error()),
+            }
+        })();
+
+        // SAFETY: it's ours
+        unsafe { libc::close(ruleset) };
+        restricted
+    }
+}
+
+mod seccomp {
+    use libc::BPF_ABS;
+    use libc::BPF_JEQ;
+    use libc::BPF_JGE;
+    use libc::BPF_JMP;
+    use libc::BPF_K;
+    use libc::BPF_LD;
+    use libc::BPF_RET;
+    use libc::BPF_W;
+    use libc::sock_filter;
+
+    #[cfg(target_arch = "x86_64")]
+    const ARCH: Option<u32> = Some(0xc000_003e);
+    #[cfg(target_arch = "aarch64")]
+    const ARCH: Option<u32> = Some(0xc000_00b7);
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    const ARCH: Option<u32> = None;
+
+    /// Syscalls above this are the x32 abi, which would get around the filter.
+    const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+    /// Refused with EPERM, unless it's allowed the network.
+    const NETWORK: &[libc::c_long] = &[libc::SYS_socket, libc::SYS_socketpair];
+
+    /// Refused with EPERM: debugging other processes, changing the system, and io_uring, whose
+    /// operations (e.g. opening sockets) aren't seen by this filter.
+    const DENIED: &[libc::c_long] = &[
+        libc::SYS_io_uring_setup,
+        libc::SYS_io_uring_enter,
+        libc::SYS_io_uring_register,
+        libc::SYS_ptrace,
+        libc::SYS_process_vm_readv,
+        libc::SYS_process_vm_writev,
+        libc::SYS_mount,
+        libc::SYS_umount2,
+        libc::SYS_pivot_root,
+        libc::SYS_chroot,
+        libc::SYS_unshare,
+        libc::SYS_setns,
+        libc::SYS_bpf,
+        libc::SYS_perf_event_open,
+        libc::SYS_userfaultfd,
+        libc::SYS_keyctl,
+        libc::SYS_add_key,
+        libc::SYS_request_key,
+        libc::SYS_kexec_load,
+        libc::SYS_init_module,
+        libc::SYS_finit_module,
+        libc::SYS_delete_module,
+        libc::SYS_reboot,
+        libc::SYS_swapon,
+        libc::SYS_swapoff,
+    ];
+
+    /// `seccomp_data` offsets
+    const NR: u32 = 0;
+    const ARCH_OFFSET: u32 = 4;
+
+    /// A deny-list filter, where we know the architecture.
+    pub fn filter(network: bool) -> Option<Vec<sock_filter>> {
+        let arch = ARCH?;
+        let mut filter = vec![
+            statement(BPF_LD | BPF_W | BPF_ABS, ARCH_OFFSET),
+            jump(BPF_JMP | BPF_JEQ | BPF_K, arch, 1, 0),
+            statement(BPF_RET | BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+            statement(BPF_LD | BPF_W | BPF_ABS, NR),
+            jump(BPF_JMP | BPF_JGE | BPF_K, X32_SYSCALL_BIT, 0, 1),
+            statement(BPF_RET | BPF_K, libc::SECCOMP_RET_KILL_PROCESS),
+        ];
+
+        let network = if network { &[][..] } else { NETWORK };
+        for &nr in network.iter().chain(DENIED) {
+            filter.push(jump(BPF_JMP | BPF_JEQ | BPF_K, nr as u32, 0, 1));
+            filter.push(statement(
+                BPF_RET | BPF_K,
+                libc::SECCOMP_RET_ERRNO | libc::EPERM as u32,
+            ));
+        }
+
+        filter.push(statement(BPF_RET | BPF_K, libc::SECCOMP_RET_ALLOW));
+        Some(filter)
+    }
+
+    fn statement(code: u32, k: u32) -> sock_filter {
+        jump(code, k, 0, 0)
+    }
+
+    fn jump(code: u32, k: u32, jt: u8, jf: u8) -> sock_filter {
+        sock_filter {
+            code: code as u16,
+            jt,
+            jf,
+            k,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::time::Duration;
+
+    use tokio::io::AsyncBufReadExt;
+    use tokio::io::BufReader;
+
+    use super::Limits;
+    use super::home;
+    use super::kill;
+    use super::landlock;
+    use super::run;
+    use super::spawn;
+
+    #[tokio::test]
+    async fn sandboxed() {
+        let limits = Limits {
+            timeout: Duration::from_secs(5),
+            cpu_secs: 5,
+            memory: 256 * 1024 * 1024,
+            file_size: 1024 * 1024,
+            network: false,
+        };
+        let home = home().unwrap();
+        let mut child = spawn(
+            "sh",
+            &["-c", "echo started; sleep 60"],
+            home.path(),
+            &limits,
+        )
+        .unwrap();
+        let mut stdout = BufReader::new(child.stdout.take().unwrap()).lines();
+        assert_eq!(
+            Some("started".to_string()),
+            stdout.next_line().await.unwrap()
+        );
+
+        kill(&child);
+        let status = tokio::time::timeout(limits.timeout, child.wait()).await;
+        assert!(!status.unwrap().unwrap().success());
+        // the sleep went too, so nothing is holding stdout open
+        assert_eq!(None, stdout.next_line().await.unwrap());
+
+        let finished = run("tr", &["a-z", "A-Z"], "hello", &limits).await.unwrap();
+        assert!(finished.status.success());
+        assert_eq!("HELLO", finished.output);
+
+        let impatient = Limits {
+            timeout: Duration::from_millis(100),
+            ..limits
+        };
+        assert!(run("sleep", &["60"], "", &impatient).await.is_err());
+
+        // its own home, and nowhere else, is writable; and the bot's files are out of reach
+        let escape = format!(
+            "cd && echo mine > file && cat file; touch {0}/escaped; cat {0}/Cargo.toml",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        let finished = run("sh", &["-c", &escape], "", &limits).await.unwrap();
+        assert!(finished.output.starts_with("mine\n"), "{}", finished.output);
+        if landlock::rules(home.path()).is_none() {
+            // the kernel can't keep it in; `rules` has already warned about that
+            return;
+        }
+        assert!(
+            !finished.output.contains("[package]"),
+            "{}",
+            finished.output
+        );
+        assert!(
+            !Path::new(env!("CARGO_MANIFEST_DIR"))
+                .join("escaped")
+                .exists()
+        );
+    }
+}
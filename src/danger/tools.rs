@@ -0,0 +1,125 @@
+use std::time::Duration;
+
+use anyhow::Result;
+use anyhow::bail;
+use anyhow::format_err;
+use regex::Regex;
+use tokio::sync::Semaphore;
+
+use super::sandbox;
+use super::sandbox::Limits;
+use crate::commands;
+use crate::config;
+use crate::config::ToolInput;
+use crate::titles::cleanup_newlines;
+
+lazy_static::lazy_static! {
+    /// colours and cursor movement, which some programs emit even without a terminal
+    static ref ESCAPES: Regex = Regex::new(r"\x1b\[[0-9;?]*[A-Za-z]").unwrap();
+}
+
+/// The `[[tool]]` programs, each started afresh, in its own sandbox, for every use.
+pub struct Tools {
+    tools: Vec<Tool>,
+}
+
+struct Tool {
+    config: config::Tool,
+    running: Semaphore,
+    limits: Limits,
+}
+
+impl Tools {
+    pub fn new(config: &[config::Tool]) -> Tools {
+        Tools {
+            tools: config
+                .iter()
+                .map(|tool| Tool {
+                    running: Semaphore::new(tool.sandbox.concurrency),
+                    limits: Limits {
+                        timeout: Duration::from_millis(tool.sandbox.timeout_ms),
+                        cpu_secs: tool.sandbox.cpu_secs,
+                        memory: tool.sandbox.memory_mb * 1024 * 1024,
+                        file_size: tool.sandbox.file_size_kb * 1024,
+                        network: tool.sandbox.network,
+                    },
+                    config: tool.clone(),
+                })
+                .collect(),
+        }
+    }
+
+    /// The output of the `index`th tool, given the user's text, tidied for IRC.
+    pub async fn run(&self, index: usize, input: &str) -> Result<String> {
+        let tool = &self.tools[index];
+        let config = &tool.config;
+        let (program, fixed) = config
+            .command
+            .split_first()
+            .ok_or_else(|| format_err!("{:?} has no command", config.name))?;
+
+        let given = match config.input {
+            ToolInput::Argument if !input.is_empty() => vec![input.to_string()],
+            ToolInput::Argument | ToolInput::Stdin => Vec::new(),
+            ToolInput::Words => commands::words(input),
+        };
+        // e.g. `-f /path/to/bot.toml`; everything it can read, it could show
+        if !config.allow_options && given.iter().any(|arg| arg.starts_with('-')) {
+            return Ok("Options aren't allowed.".to_string());
+        }
+        let stdin = match config.input {
+            ToolInput::Stdin => input,
+            _ => "",
+        };
+        let args: Vec<&String> = fixed.iter().chain(&given).collect();
+
+        let _permit = tool.running.acquire().await?;
+        let finished = sandbox::run(program, &args, stdin, &tool.limits).await?;
+        let output = ESCAPES.replace_all(&finished.output, "");
+        if output.trim().is_empty() {
+            if !finished.status.success() {
+                bail!("{:?} failed: {}", config.name, finished.status);
+            }
+            return Ok("(no output)".to_string());
+        }
+
+        Ok(if config.multiline {
+            output.trim_end().to_string()
+        } else {
+            cleanup_newlines(&output)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Tools;
+    use crate::config;
+    use crate::config::ToolInput;
+
+    #[tokio::test]
+    async fn running() {
+        let tool = |input, command: &[&str]| config::Tool {
+            name: "test".to_string(),
+            command: command.iter().map(|s| s.to_string()).collect(),
+            input,
+            aliases: Vec::new(),
+            usage: String::new(),
+            help: String::new(),
+            min_args: 0,
+            allow_options: false,
+            multiline: false,
+            sandbox: config::Sandbox::default(),
+        };
+        let tools = Tools::new(&[
+            tool(ToolInput::Words, &["printf", "%s\n"]),
+            tool(ToolInput::Stdin, &["tr", "a-z", "A-Z"]),
+            tool(ToolInput::Argument, &["false"]),
+        ]);
+
+        assert_eq!("a ¶ b c", tools.run(0, r#"a "b c""#).await.unwrap());
+        assert_eq!("Options aren't allowed.", tools.run(0, "-v").await.unwrap());
+        assert_eq!("HELLO", tools.run(1, "hello").await.unwrap());
+        assert!(tools.run(2, "").await.is_err());
+    }
+}
@@ -0,0 +1,70 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::Context as _;
+use anyhow::Result;
+use anyhow::format_err;
+use rusqlite::Connection;
+
+use crate::remind;
+use crate::seen;
+use crate::tell;
+use crate::urls;
+
+/// Where features keep what they remember; tables are created as needed.
+const SCHEMAS: &[&str] = &[seen::SCHEMA, tell::SCHEMA, remind::SCHEMA, urls::SCHEMA];
+
+/// The bot's database, shared between networks; every use is short, so it's simply locked.
+pub struct Db {
+    connection: Mutex<Connection>,
+}
+
+impl Db {
+    /// In `data_dir`, or in memory, and forgotten on exit, without one.
+    pub fn open(data_dir: Option<&Path>) -> Result<Db> {
+        let connection = match data_dir {
+            Some(dir) => {
+                fs::create_dir_all(dir).with_context(|| format_err!("creating {:?}", dir))?;
+                let path = dir.join("unsnap.sqlite");
+                let connection =
+                    Connection::open(&path).with_context(|| format_err!("opening {:?}", path))?;
+                connection.pragma_update(None, "journal_mode", "wal")?;
+                connection
+            }
+            None => Connection::open_in_memory()?,
+        };
+
+        for schema in SCHEMAS {
+            connection.execute_batch(schema)?;
+        }
+
+        Ok(Db {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    pub fn with<T>(&self, work: impl FnOnce(&Connection) -> rusqlite::Result<T>) -> Result<T> {
+        Ok(work(&self.connection.lock().expect("poisoned"))?)
+    }
+}
+
+/// Runs `work` on the blocking pool: the database can wait on the disk, which would hold up
+/// everything else on an async task.
+pub async fn blocking<T: Send + 'static>(
+    work: impl FnOnce() -> Result<T> + Send + 'static,
+) -> Result<T> {
+    tokio::task::spawn_blocking(work).await?
+}
+
+/// An empty database, for tests.
+#[cfg(test)]
+pub fn scratch() -> std::sync::Arc<Db> {
+    std::sync::Arc::new(Db::open(None).expect("in memory"))
+}
+
+/// When things happen in tests, so ages come out the same every time.
+#[cfg(test)]
+pub fn some_time() -> chrono::DateTime<chrono::Utc> {
+    chrono::DateTime::from_timestamp(1_800_000_000, 0).expect("in range")
+}
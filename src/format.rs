@@ -0,0 +1,84 @@
+use std::borrow::Cow;
+
+use chrono::TimeDelta;
+use regex::Regex;
+
+lazy_static::lazy_static! {
+    // mIRC colours (\x03 fg,bg), hex colours (\x04), CTCP delimiters, and the toggles:
+    // bold, reset, monospace, reverse, italic, strikethrough, underline
+    pub static ref FORMATTING: Regex = Regex::new(concat!(
+        r"\x03(?:\d{1,2}(?:,\d{1,2})?)?",
+        r"|\x04(?:[0-9a-fA-F]{6}(?:,[0-9a-fA-F]{6})?)?",
+        r"|[\x01\x02\x0f\x11\x16\x1d\x1e\x1f]",
+    ))
+    .unwrap();
+}
+
+const BOLD: char = '\x02';
+const COLOUR: char = '\x03';
+
+/// mIRC colour numbers.
+pub const RED: u8 = 4;
+
+pub fn bold(text: &str) -> String {
+    format!("{}{}{}", BOLD, text, BOLD)
+}
+
+pub fn colour(text: &str, colour: u8) -> String {
+    // always two digits, so text starting with a digit isn't eaten
+    format!("{}{:02}{}{}", COLOUR, colour, text, COLOUR)
+}
+
+/// The bytes `colour` adds.
+pub const COLOUR_OVERHEAD: usize = 4;
+
+/// The text, as a client that ignores formatting would show it.
+pub fn strip(text: &str) -> Cow<'_, str> {
+    FORMATTING.replace_all(text, "")
+}
+
+/// How long, in the two largest units, e.g. `3 days, 2 hours`.
+pub fn ago(elapsed: TimeDelta) -> String {
+    let seconds = elapsed.num_seconds().max(0);
+    let units = [
+        (seconds / 86400, "day"),
+        (seconds / 3600 % 24, "hour"),
+        (seconds / 60 % 60, "minute"),
+        (seconds % 60, "second"),
+    ];
+    let shown: Vec<String> = units
+        .iter()
+        .skip_while(|(count, _)| 0 == *count)
+        .take(2)
+        .filter(|(count, _)| 0 != *count)
+        .map(|(count, unit)| match count {
+            1 => format!("1 {}", unit),
+            count => format!("{} {}s", count, unit),
+        })
+        .collect();
+    match shown.is_empty() {
+        true => "0 seconds".to_string(),
+        false => shown.join(", "),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::COLOUR_OVERHEAD;
+    use super::RED;
+    use super::bold;
+    use super::colour;
+    use super::strip;
+
+    #[test]
+    fn round_trip() {
+        assert_eq!("\x02host\x02", bold("host"));
+        assert_eq!("\x03041st\x03", colour("1st", RED));
+        assert_eq!(COLOUR_OVERHEAD, colour("", RED).len());
+        assert_eq!(
+            "host 1st",
+            strip(&format!("{} {}", bold("host"), colour("1st", RED)))
+        );
+        assert_eq!("plain", strip("plain"));
+    }
+}
@@ -1,151 +1,96 @@
 #[macro_use]
 extern crate log;
 
+mod admin;
+mod backoff;
+mod bot;
+mod calc;
+mod caps;
+mod clock;
+mod commands;
 mod config;
 mod content;
+mod ctcp;
 mod danger;
+mod db;
+mod format;
+mod remind;
+mod sasl;
+mod seen;
+mod split;
+mod tell;
 mod titles;
+mod transport;
+mod urls;
 mod webs;
 
-use std::fs;
+use std::env;
+use std::sync::Arc;
 
-use anyhow::Context as _;
 use anyhow::Result;
+use anyhow::bail;
 use anyhow::format_err;
 use futures::prelude::*;
-use irc::client::{Sender, prelude as ic};
 use reqwest::Client;
-use std::sync::Arc;
 
+use crate::transport::console::Console;
+use crate::transport::irc::Irc;
 use crate::webs::Context;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     pretty_env_logger::try_init()?;
 
-    let config: config::Config = toml::from_str(&fs::read_to_string("bot.toml")?)?;
-
-    let irc_config = ic::Config {
-        nickname: Some(config.server.nick.to_string()),
-        server: Some(config.server.hostname.to_string()),
-        username: config.server.user.clone(),
-        channels: config.server.channels.clone(),
-        password: config.server.password.clone(),
-        nick_password: config.server.nick_password.clone(),
-
-        // freenode takes over 10s to warm up, including hostname verification failure
-        ping_timeout: Some(20),
-        ..Default::default()
-    };
-
-    let (http, context) = Context::new(config);
-    let context = Arc::new(context);
-
-    let mut client = ic::Client::from_config(irc_config).await?;
+    let config = config::Config::load()?;
 
-    client.identify()?;
+    let (http, context) = Context::new(config)?;
 
-    let mut stream = client.stream()?;
-
-    while let Some(message) = stream.next().await.transpose()? {
-        let http = http.clone();
-        let context = Arc::clone(&context);
-        if let Err(e) = handle(http, context, &client, &message).await {
-            warn!("processing error: {:?}: {:?}", message, e);
+    let mut args = env::args().skip(1);
+    match args.next().as_deref() {
+        None => (),
+        Some("test-rule") => {
+            let url = args
+                .next()
+                .ok_or_else(|| format_err!("usage: unsnap test-rule URL"))?;
+            return test_rule(&http, &context, &url).await;
         }
-    }
-
-    Ok(())
-}
-
-async fn handle(
-    http: Client,
-    context: Arc<Context>,
-    client: &ic::Client,
-    message: &ic::Message,
-) -> Result<()> {
-    info!("<- {:?}", message);
-
-    match message.command {
-        ic::Command::PRIVMSG(ref dest, ref msg) => {
-            if let Some(nick) = message.source_nickname() {
-                tokio::spawn(process_msg_or_log(
-                    http,
-                    dest.to_string(),
-                    client.sender(),
-                    context,
-                    nick.to_string(),
-                    msg.to_string(),
-                ));
+        Some("title") => {
+            let line = args.collect::<Vec<_>>().join(" ");
+            for title in titles::titles_for(http, Arc::new(context), &line).await? {
+                println!("{}", title);
             }
+            return Ok(());
         }
-        _ => (),
+        Some("console") => {
+            return bot::run(http, Arc::new(context), Console::new()).await;
+        }
+        Some(other) => bail!("unknown subcommand: {:?}", other),
     }
 
-    Ok(())
-}
+    let context = Arc::new(context);
+    let networks = context.config.all_networks();
 
-async fn process_msg_or_log(
-    http: Client,
-    dest: String,
-    sender: Sender,
-    context: Arc<Context>,
-    nick: String,
-    msg: String,
-) -> () {
-    if let Err(e) = process_msg(
-        http,
-        context,
-        nick.to_string(),
-        msg.to_string(),
-        move |message| {
-            sender
-                .send_privmsg(dest.to_string(), message)
-                .with_context(|| format_err!("replying to {:?}", dest))
-        },
-    )
-    .await
-    .with_context(|| format_err!("processing < {:?}> {:?}", nick, msg))
-    {
-        warn!("process_msg failed: {:?}", e)
-    }
-}
+    future::try_join_all(networks.into_iter().map(|network| {
+        let transport = Irc::new(network, &context.config);
+        bot::run(http.clone(), Arc::clone(&context), transport)
+    }))
+    .await?;
 
-async fn process_msg<F>(
-    http: Client,
-    context: Arc<Context>,
-    nick: String,
-    msg: String,
-    mut sender: F,
-) -> Result<()>
-where
-    F: FnMut(&str) -> Result<()>,
-{
-    if msg.starts_with("!qalc ") {
-        let input = &msg["!qalc".len()..];
-        match danger::qalc(input) {
-            Ok(resp) => sender(&format!("{}: {}", nick, limit_length(&resp)))?,
-            Err(e) => {
-                sender(&format!("{}: It did not work.", nick))?;
-                error!("qalc {:?} failed: {:?}", input, e);
-            }
-        }
-        return Ok(());
-    }
-
-    for title in titles::titles_for(http, context, &msg).await? {
-        assert!(!title.contains(|c: char| c.is_control()));
-        sender(limit_length(&title))?;
-    }
     Ok(())
 }
 
-fn limit_length(val: &str) -> &str {
-    for end in 365..400 {
-        if val.is_char_boundary(end) {
-            return &val[..end];
-        }
+async fn test_rule(http: &Client, context: &Context, url: &str) -> Result<()> {
+    let titling = context.titling();
+    let (rule, vars) = titling
+        .rules
+        .find(url)
+        .ok_or_else(|| format_err!("no rule matches {:?}", url))?;
+
+    println!("matched rule for {:?}", rule.host);
+    match rule.apply(http, url, vars).await? {
+        Some(title) => println!("{}", title),
+        None => println!("rule found nothing"),
     }
 
-    val
+    Ok(())
 }
@@ -0,0 +1,362 @@
+//! `!remind`: messages to send later, to the person who asked or to a channel.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use chrono::DateTime;
+use chrono::TimeDelta;
+use chrono::Utc;
+use rusqlite::OptionalExtension;
+use rusqlite::params;
+use tokio::sync::Notify;
+use tokio::sync::futures::Notified;
+
+use crate::clock;
+use crate::commands::Caller;
+use crate::config;
+use crate::db::Db;
+
+pub const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS reminders (
+        id INTEGER PRIMARY KEY,
+        network TEXT NOT NULL,
+        owner TEXT NOT NULL,
+        -- lower-cased account, or nick, for quotas, listing and cancelling
+        owner_key TEXT NOT NULL,
+        -- a channel, or the owner's nick
+        target TEXT NOT NULL,
+        -- for the owner, rather than an announcement
+        personal INTEGER NOT NULL,
+        text TEXT NOT NULL,
+        -- unix seconds
+        due INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS reminders_due ON reminders (network, due);
+";
+
+/// A reminder which has come due.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reminder {
+    pub id: i64,
+    pub owner: String,
+    pub target: String,
+    pub personal: bool,
+    pub text: String,
+}
+
+impl Reminder {
+    /// What's said when it comes due.
+    pub fn line(&self) -> String {
+        match self.personal {
+            true => format!("{}: reminder: {}", self.owner, self.text),
+            false => format!("Reminder from {}: {}", self.owner, self.text),
+        }
+    }
+}
+
+pub struct Reminders {
+    db: Arc<Db>,
+    config: config::Remind,
+    time: config::Time,
+    changed: Notify,
+}
+
+impl Reminders {
+    pub fn new(db: Arc<Db>, config: &config::Remind, time: &config::Time) -> Reminders {
+        Reminders {
+            db,
+            config: config.clone(),
+            time: time.clone(),
+            changed: Notify::new(),
+        }
+    }
+
+    /// `!remind me in 2h to ...`, `!remind #chan at 17:00 ...`, `!remind list` or
+    /// `!remind cancel 3`; `here` is where it was asked, and other channels are only allowed
+    /// with `anywhere`.
+    pub fn answer(
+        &self,
+        caller: &Caller,
+        here: &str,
+        anywhere: bool,
+        args: &str,
+        now: DateTime<Utc>,
+    ) -> Result<String> {
+        if !self.config.enabled {
+            return Ok("I'm not keeping reminders.".to_string());
+        }
+
+        let owner_key = caller.account.unwrap_or(caller.nick).to_lowercase();
+        let (first, rest) = args.trim().split_once(' ').unwrap_or((args.trim(), ""));
+        let rest = rest.trim();
+        let (target, personal) = match first {
+            "list" => return self.list(caller, &owner_key),
+            "cancel" | "delete" => return self.cancel(caller.network, &owner_key, rest),
+            "me" => (here, true),
+            channel if channel.starts_with(['#', '&']) => {
+                if !anywhere && !channel.eq_ignore_ascii_case(here) {
+                    return Ok("I can only remind the channel you're in.".to_string());
+                }
+                (channel, false)
+            }
+            _ => return Ok("Remind who? Try me, or a #channel.".to_string()),
+        };
+
+        let furthest = TimeDelta::days(self.config.max_days as i64);
+        let too_far = || {
+            format!(
+                "That's too far away; I only remember {} days ahead.",
+                self.config.max_days
+            )
+        };
+        let (due, text) = match rest.strip_prefix("in ") {
+            Some(rest) => {
+                let mut words = rest.split_whitespace().peekable();
+                let mut delay = TimeDelta::zero();
+                while let Some(more) = words.peek().and_then(|word| clock::delay(word)) {
+                    // checked as it grows, so it can't overflow
+                    delay = match more.ok().and_then(|more| delay.checked_add(&more)) {
+                        Some(delay) if delay <= furthest => delay,
+                        _ => return Ok(too_far()),
+                    };
+                    words.next();
+                }
+                if delay.is_zero() {
+                    return Ok("In how long? Try 2h30m, or 1d.".to_string());
+                }
+                (now + delay, words.collect::<Vec<_>>().join(" "))
+            }
+            None => match clock::upcoming(&self.time, caller.account, caller.nick, rest, now) {
+                Ok(upcoming) => upcoming,
+                Err(e) => return Ok(e.to_string()),
+            },
+        };
+
+        let text = text.strip_prefix("to ").unwrap_or(&text).trim();
+        if text.is_empty() {
+            return Ok("Remind you of what?".to_string());
+        }
+        if due > now + furthest {
+            return Ok(too_far());
+        }
+
+        let waiting: usize = self.db.with(|db| {
+            db.query_row(
+                "SELECT COUNT(*) FROM reminders WHERE network = ?1 AND owner_key = ?2",
+                params![caller.network, owner_key],
+                |row| row.get(0),
+            )
+        })?;
+        if waiting >= self.config.max_per_person {
+            return Ok(format!(
+                "You already have {} reminders; cancel some first.",
+                waiting
+            ));
+        }
+
+        let id = self.db.with(|db| {
+            db.execute(
+                "INSERT INTO reminders (network, owner, owner_key, target, personal, text, due)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    caller.network,
+                    caller.nick,
+                    owner_key,
+                    target,
+                    personal,
+                    text,
+                    due.timestamp()
+                ],
+            )?;
+            Ok(db.last_insert_rowid())
+        })?;
+        self.changed.notify_waiters();
+
+        let when = clock::show_for(&self.time, caller.account, caller.nick, due)?;
+        Ok(match personal {
+            true => format!("Okay, I'll remind you at {} (#{}).", when, id),
+            false => format!("Okay, I'll remind {} at {} (#{}).", target, when, id),
+        })
+    }
+
+    fn list(&self, caller: &Caller, owner_key: &str) -> Result<String> {
+        let waiting: Vec<(i64, String, bool, String, i64)> = self.db.with(|db| {
+            let mut statement = db.prepare_cached(
+                "SELECT id, target, personal, text, due FROM reminders
+                 WHERE network = ?1 AND owner_key = ?2
+                 ORDER BY due",
+            )?;
+            statement
+                .query_map(params![caller.network, owner_key], |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                    ))
+                })?
+                .collect()
+        })?;
+        if waiting.is_empty() {
+            return Ok("You don't have any reminders.".to_string());
+        }
+
+        let mut shown = Vec::with_capacity(waiting.len());
+        for (id, target, personal, text, due) in waiting {
+            let due = DateTime::from_timestamp(due, 0).unwrap_or_default();
+            let when = clock::show_for(&self.time, caller.account, caller.nick, due)?;
+            shown.push(match personal {
+                true => format!("#{} {}: {}", id, when, text),
+                false => format!("#{} {}, in {}: {}", id, when, target, text),
+            });
+        }
+        Ok(shown.join("; "))
+    }
+
+    fn cancel(&self, network: &str, owner_key: &str, id: &str) -> Result<String> {
+        let Ok(id) = id.trim_start_matches('#').parse::<i64>() else {
+            return Ok("Cancel which? The number is in !remind list.".to_string());
+        };
+        let deleted = self.db.with(|db| {
+            db.execute(
+                "DELETE FROM reminders WHERE id = ?1 AND network = ?2 AND owner_key = ?3",
+                params![id, network, owner_key],
+            )
+        })?;
+        self.changed.notify_waiters();
+        Ok(match deleted {
+            0 => format!("You don't have a reminder #{}.", id),
+            _ => format!("Cancelled #{}.", id),
+        })
+    }
+
+    /// Resolves when a reminder is set or cancelled.
+    pub fn changed(&self) -> Notified<'_> {
+        self.changed.notified()
+    }
+
+    /// When the next reminder on the network is due.
+    pub fn next(&self, network: &str) -> Result<Option<DateTime<Utc>>> {
+        let due: Option<i64> = self.db.with(|db| {
+            db.query_row(
+                "SELECT MIN(due) FROM reminders WHERE network = ?1",
+                params![network],
+                |row| row.get(0),
+            )
+            .optional()
+            .map(Option::flatten)
+        })?;
+        Ok(due.and_then(|due| DateTime::from_timestamp(due, 0)))
+    }
+
+    /// Reminders which have come due, earliest first; they stay until they're `done`.
+    pub fn due(&self, network: &str, now: DateTime<Utc>) -> Result<Vec<Reminder>> {
+        self.db.with(|db| {
+            let mut statement = db.prepare_cached(
+                "SELECT id, owner, target, personal, text FROM reminders
+                 WHERE network = ?1 AND due <= ?2
+                 ORDER BY due",
+            )?;
+            statement
+                .query_map(params![network, now.timestamp()], |row| {
+                    Ok(Reminder {
+                        id: row.get(0)?,
+                        owner: row.get(1)?,
+                        target: row.get(2)?,
+                        personal: row.get(3)?,
+                        text: row.get(4)?,
+                    })
+                })?
+                .collect()
+        })
+    }
+
+    /// Forgets a reminder which has been sent.
+    pub fn done(&self, reminder: &Reminder) -> Result<()> {
+        self.db
+            .with(|db| db.execute("DELETE FROM reminders WHERE id = ?1", params![reminder.id]))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+    use chrono::TimeDelta;
+
+    use super::Reminders;
+    use crate::commands::Caller;
+    use crate::config;
+    use crate::db;
+
+    #[test]
+    fn reminding() {
+        let mut time = config::Time::default();
+        time.homes
+            .insert("faux".to_string(), "Europe/London".to_string());
+        let reminders = Reminders::new(
+            db::scratch(),
+            &config::Remind {
+                max_per_person: 2,
+                ..config::Remind::default()
+            },
+            &time,
+        );
+        let now = DateTime::parse_from_rfc3339("2026-10-19T12:00:00Z")
+            .unwrap()
+            .to_utc();
+        let remind = |nick, args| {
+            let caller = Caller {
+                network: "net",
+                nick,
+                account: None,
+                hostmask: None,
+            };
+            reminders
+                .answer(&caller, "#chan", false, args, now)
+                .unwrap()
+        };
+
+        // already past today, and in another zone
+        assert_eq!(
+            "Okay, I'll remind #chan at Tue 20 Oct 07:00 BST (Europe/London) (#1).",
+            remind("faux", "#chan at 8:00 Europe/Berlin standup")
+        );
+        assert_eq!(
+            "I can only remind the channel you're in.",
+            remind("faux", "#other in 1h hi")
+        );
+        for far in [
+            "me in 100000000w x",
+            "me in 1000w x",
+            "me in 99999w 99999w x",
+        ] {
+            assert!(
+                remind("faux", far).starts_with("That's too far away"),
+                "{}",
+                far
+            );
+        }
+        remind("faux", "me in 2h30m to deploy");
+        assert!(remind("Faux", "me in 1h x").starts_with("You already have 2"));
+
+        // only the owner can see or cancel them
+        assert_eq!("You don't have any reminders.", remind("bob", "list"));
+        assert_eq!("You don't have a reminder #1.", remind("bob", "cancel 1"));
+
+        // due ones stay until they've been sent
+        assert!(reminders.due("net", now).unwrap().is_empty());
+        let later = now + TimeDelta::hours(3);
+        let due = reminders.due("net", later).unwrap();
+        assert_eq!(
+            vec!["faux: reminder: deploy"],
+            due.iter().map(|r| r.line()).collect::<Vec<_>>()
+        );
+        assert_eq!(due, reminders.due("net", later).unwrap());
+        reminders.done(&due[0]).unwrap();
+
+        assert_eq!("Cancelled #1.", remind("faux", "cancel #1"));
+        assert_eq!(None, reminders.next("net").unwrap());
+    }
+}
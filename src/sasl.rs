@@ -0,0 +1,97 @@
+use anyhow::Result;
+use anyhow::bail;
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use irc::client::prelude as ic;
+use irc::proto::CapSubCommand;
+
+use crate::config::Sasl;
+use crate::config::Server;
+
+/// AUTHENTICATE payloads longer than this must be split.
+const CHUNK: usize = 400;
+
+/// Starts authentication, once the server has agreed to the capability.
+pub fn begin(client: &ic::Client, sasl: &Sasl) -> Result<()> {
+    match sasl {
+        Sasl::Plain => client.send_sasl_plain()?,
+        Sasl::External => client.send_sasl_external()?,
+    }
+    Ok(())
+}
+
+/// Progresses authentication; an error means we're never going to get in.
+pub fn handle(client: &ic::Client, server: &Server, message: &ic::Message) -> Result<()> {
+    let sasl = match &server.sasl {
+        Some(sasl) => sasl,
+        None => return Ok(()),
+    };
+
+    match message.command {
+        ic::Command::AUTHENTICATE(ref challenge) if challenge == "+" => {
+            let payload = match sasl {
+                Sasl::Plain => plain(server),
+                // the identity comes from the client certificate
+                Sasl::External => String::new(),
+            };
+            for chunk in chunks(&payload) {
+                client.send_sasl(chunk)?;
+            }
+        }
+        ic::Command::Response(ic::Response::RPL_SASLSUCCESS, _) => {
+            info!("sasl authentication succeeded");
+            client.send(ic::Command::CAP(None, CapSubCommand::END, None, None))?;
+        }
+        ic::Command::Response(
+            ic::Response::ERR_SASLFAIL
+            | ic::Response::ERR_SASLTOOLONG
+            | ic::Response::ERR_SASLABORT
+            | ic::Response::ERR_NICKLOCKED,
+            ref args,
+        ) => bail!("sasl authentication failed: {:?}", args),
+        _ => (),
+    }
+
+    Ok(())
+}
+
+fn plain(server: &Server) -> String {
+    let user = server.sasl_username.as_ref().unwrap_or(&server.nick);
+    let password = server
+        .sasl_password
+        .as_ref()
+        .or(server.nick_password.as_ref())
+        .expect("validated at startup");
+    BASE64.encode(format!("{}\0{}\0{}", user, user, password))
+}
+
+/// The AUTHENTICATE lines for a payload, including the terminating `+` when needed.
+fn chunks(payload: &str) -> Vec<&str> {
+    let mut chunks: Vec<&str> = payload
+        .as_bytes()
+        .chunks(CHUNK)
+        // base64 is ascii
+        .map(|chunk| std::str::from_utf8(chunk).expect("ascii"))
+        .collect();
+    if payload.len().is_multiple_of(CHUNK) {
+        chunks.push("+");
+    }
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::chunks;
+
+    #[test]
+    fn chunking() {
+        assert_eq!(vec!["+"], chunks(""));
+        assert_eq!(vec!["abc"], chunks("abc"));
+
+        let exact = "a".repeat(400);
+        assert_eq!(vec![exact.as_str(), "+"], chunks(&exact));
+
+        let long = "a".repeat(401);
+        assert_eq!(vec![&long[..400], "a"], chunks(&long));
+    }
+}
@@ -0,0 +1,341 @@
+//! `!seen`: when each nick last spoke, joined, left or changed name, and where.
+
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Result;
+use chrono::DateTime;
+use chrono::TimeDelta;
+use chrono::Utc;
+use rusqlite::OptionalExtension;
+use rusqlite::params;
+
+use crate::config;
+use crate::db::Db;
+use crate::format;
+use crate::split;
+use crate::transport::Activity;
+use crate::transport::Doing;
+use crate::transport::Message;
+
+pub const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS seen (
+        network TEXT NOT NULL,
+        -- lower-cased
+        nick TEXT NOT NULL,
+        -- empty for quits and nick changes, which aren't in any one channel
+        channel TEXT NOT NULL,
+        shown_nick TEXT NOT NULL,
+        what TEXT NOT NULL,
+        detail TEXT NOT NULL,
+        -- unix seconds
+        at INTEGER NOT NULL,
+        PRIMARY KEY (network, nick, channel)
+    );
+    CREATE INDEX IF NOT EXISTS seen_at ON seen (at);
+    CREATE TABLE IF NOT EXISTS seen_opt_out (
+        network TEXT NOT NULL,
+        nick TEXT NOT NULL,
+        PRIMARY KEY (network, nick)
+    );
+";
+
+/// What people said is kept only up to this many bytes.
+const MAX_DETAIL: usize = 300;
+
+/// How often old activity is deleted.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+pub struct Seen {
+    db: Arc<Db>,
+    config: config::Seen,
+    purged: Mutex<Option<Instant>>,
+}
+
+impl Seen {
+    pub fn new(db: Arc<Db>, config: &config::Seen) -> Seen {
+        Seen {
+            db,
+            config: config.clone(),
+            purged: Mutex::default(),
+        }
+    }
+
+    /// Remembers someone speaking in a channel.
+    pub fn message(&self, network: &str, message: &Message) -> Result<()> {
+        let detail = match (message.action, self.config.quote) {
+            (_, false) => String::new(),
+            (false, true) => message.text.to_string(),
+            (true, true) => format!("* {} {}", message.source, message.text),
+        };
+        self.record(
+            network,
+            &message.source,
+            &message.target,
+            "said",
+            &detail,
+            message.time.unwrap_or_else(Utc::now),
+        )
+    }
+
+    /// Remembers someone arriving, leaving or changing name.
+    pub fn activity(&self, network: &str, activity: &Activity) -> Result<()> {
+        let nick = &activity.nick;
+        let at = activity.time.unwrap_or_else(Utc::now);
+        let with_reason = |reason: &str| match reason {
+            "" => String::new(),
+            reason => format!("({})", reason),
+        };
+        match &activity.doing {
+            Doing::Joined { channel } => self.record(network, nick, channel, "joined", "", at),
+            Doing::Parted { channel, reason } => {
+                self.record(network, nick, channel, "parted", &with_reason(reason), at)
+            }
+            Doing::Kicked {
+                channel,
+                by,
+                reason,
+            } => {
+                let detail = format!("by {} {}", by, with_reason(reason));
+                self.record(network, nick, channel, "kicked", detail.trim_end(), at)
+            }
+            Doing::Quit { reason, .. } => {
+                self.record(network, nick, "", "quit", &with_reason(reason), at)
+            }
+            Doing::Renamed { to, .. } => {
+                self.record(network, nick, "", "renamed to", to, at)?;
+                self.record(network, to, "", "renamed from", nick, at)
+            }
+        }
+    }
+
+    fn record(
+        &self,
+        network: &str,
+        nick: &str,
+        channel: &str,
+        what: &str,
+        detail: &str,
+        at: DateTime<Utc>,
+    ) -> Result<()> {
+        if !self.config.enabled
+            || self
+                .config
+                .ignore
+                .iter()
+                .any(|ignored| ignored.eq_ignore_ascii_case(nick))
+        {
+            return Ok(());
+        }
+
+        self.purge()?;
+        self.db.with(|db| {
+            db.execute(
+                "INSERT OR REPLACE INTO seen (network, nick, channel, shown_nick, what, detail, at)
+                 SELECT ?1, ?2, ?3, ?4, ?5, ?6, ?7
+                 WHERE NOT EXISTS (SELECT 1 FROM seen_opt_out WHERE network = ?1 AND nick = ?2)",
+                params![
+                    network,
+                    nick.to_lowercase(),
+                    channel.to_lowercase(),
+                    nick,
+                    what,
+                    split::elide(detail, MAX_DETAIL),
+                    at.timestamp()
+                ],
+            )
+        })?;
+        Ok(())
+    }
+
+    /// Forgets activity older than the retention, at most every `PURGE_INTERVAL`.
+    fn purge(&self) -> Result<()> {
+        let mut purged = self.purged.lock().expect("poisoned");
+        if purged.is_some_and(|when| when.elapsed() < PURGE_INTERVAL) {
+            return Ok(());
+        }
+        *purged = Some(Instant::now());
+        let deleted = self.db.with(|db| {
+            db.execute(
+                "DELETE FROM seen WHERE at < ?1",
+                params![self.cutoff(Utc::now()).timestamp()],
+            )
+        })?;
+        if deleted > 0 {
+            info!("forgot {} old !seen entries", deleted);
+        }
+        Ok(())
+    }
+
+    fn cutoff(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        now - TimeDelta::days(self.config.retention_days as i64)
+    }
+
+    /// `!seen nick`, for what they did in the channel it's asked in, or anywhere for quits and
+    /// nick changes; or `!seen off` and `!seen on`, for the asker.
+    pub fn answer(
+        &self,
+        network: &str,
+        channel: Option<&str>,
+        asker: &str,
+        args: &str,
+        now: DateTime<Utc>,
+    ) -> Result<String> {
+        if !self.config.enabled {
+            return Ok("I'm not keeping track of anyone.".to_string());
+        }
+
+        let nick = args.split_whitespace().next().unwrap_or_default();
+        match nick {
+            "off" => {
+                self.db.with(|db| {
+                    let asker = asker.to_lowercase();
+                    db.execute(
+                        "INSERT OR IGNORE INTO seen_opt_out (network, nick) VALUES (?1, ?2)",
+                        params![network, asker],
+                    )?;
+                    db.execute(
+                        "DELETE FROM seen WHERE network = ?1 AND nick = ?2",
+                        params![network, asker],
+                    )
+                })?;
+                return Ok(
+                    "I've forgotten when I saw you, and won't keep track of you.".to_string(),
+                );
+            }
+            "on" => {
+                self.db.with(|db| {
+                    db.execute(
+                        "DELETE FROM seen_opt_out WHERE network = ?1 AND nick = ?2",
+                        params![network, asker.to_lowercase()],
+                    )
+                })?;
+                return Ok("I'll remember when I last saw you.".to_string());
+            }
+            _ => (),
+        }
+
+        // what someone said in one channel is none of another's business
+        let Some(channel) = channel else {
+            return Ok("Ask in a channel; I only say what I saw there.".to_string());
+        };
+
+        let last = self.db.with(|db| {
+            db.query_row(
+                "SELECT shown_nick, channel, what, detail, at FROM seen
+                 WHERE network = ?1 AND nick = ?2 AND at >= ?3 AND channel IN (?4, '')
+                 ORDER BY at DESC LIMIT 1",
+                params![
+                    network,
+                    nick.to_lowercase(),
+                    self.cutoff(now).timestamp(),
+                    channel.to_lowercase()
+                ],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, String>(3)?,
+                        row.get::<_, i64>(4)?,
+                    ))
+                },
+            )
+            .optional()
+        })?;
+
+        let Some((shown, channel, what, detail, at)) = last else {
+            return Ok(format!("I haven't seen {}.", nick));
+        };
+        let at = DateTime::from_timestamp(at, 0).unwrap_or(now);
+
+        let doing = match (what.as_str(), detail.as_str()) {
+            ("said", "") => format!("talking in {}", channel),
+            ("said", text) => format!("in {}, saying: {}", channel, text),
+            ("joined", _) => format!("joining {}", channel),
+            ("parted", reason) => format!("leaving {} {}", channel, reason),
+            ("kicked", by) => format!("being kicked from {} {}", channel, by),
+            // the reason went to every channel they were in, not only this one
+            ("quit", _) => "quitting".to_string(),
+            ("renamed to", nick) => format!("changing nick to {}", nick),
+            ("renamed from", nick) => format!("changing nick from {}", nick),
+            (what, detail) => format!("{} {}", what, detail),
+        };
+        Ok(format!(
+            "{} was last seen {} ago ({}), {}",
+            shown,
+            format::ago(now - at),
+            at.format("%Y-%m-%d %H:%M UTC"),
+            doing.trim_end()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::Seen;
+    use crate::config;
+    use crate::db;
+    use crate::transport::Activity;
+    use crate::transport::Doing;
+    use crate::transport::Message;
+
+    #[test]
+    fn seeing() {
+        let seen = Seen::new(
+            db::scratch(),
+            &config::Seen {
+                quote: true,
+                ..config::Seen::default()
+            },
+        );
+        let then = db::some_time();
+        let said = |target: &str, text: &str| Message {
+            source: "Faux".to_string(),
+            target: target.to_string(),
+            text: text.to_string(),
+            private: false,
+            action: false,
+            id: None,
+            account: None,
+            hostmask: None,
+            time: Some(then),
+        };
+        seen.message("net", &said("#chan", "hello")).unwrap();
+        seen.message("net", &said("#secret", "the plan")).unwrap();
+
+        let now = then + TimeDelta::seconds(3 * 3600 + 125);
+        let answer = |channel, args| seen.answer("net", channel, "x", args, now).unwrap();
+        assert_eq!(
+            "Faux was last seen 3 hours, 2 minutes ago (2027-01-15 08:00 UTC), in #chan, saying: hello",
+            answer(Some("#Chan"), "faux")
+        );
+        assert_eq!("I haven't seen faux.", answer(Some("#other"), "faux"));
+        assert!(answer(None, "faux").starts_with("Ask in a channel"));
+
+        // a quit is news everywhere, but the reason isn't
+        seen.activity(
+            "net",
+            &Activity {
+                nick: "faux".to_string(),
+                account: None,
+                doing: Doing::Quit {
+                    reason: "off to #secret".to_string(),
+                    channels: vec!["#chan".to_string()],
+                },
+                time: Some(then),
+            },
+        )
+        .unwrap();
+        assert!(answer(Some("#other"), "faux").ends_with(", quitting"));
+
+        seen.answer("net", None, "FAUX", "off", now).unwrap();
+        assert_eq!("I haven't seen faux.", answer(Some("#chan"), "faux"));
+        seen.message("net", &said("#chan", "back")).unwrap();
+        assert_eq!("I haven't seen faux.", answer(Some("#chan"), "faux"));
+    }
+}
@@ -0,0 +1,153 @@
+use crate::titles::cleanup_newlines;
+
+/// The whole line a server will accept, including the trailing CRLF.
+const LINE: usize = 512;
+
+/// Even with an absurd hostmask or target, say something.
+const MIN_PAYLOAD: usize = 64;
+
+const ELLIPSIS: &str = "…";
+
+/// How much text fits in a PRIVMSG, once the server has prefixed it with our hostmask.
+pub fn payload_limit(hostmask: &str, target: &str) -> usize {
+    let overhead = format!(":{} PRIVMSG {} :\r\n", hostmask, target).len();
+    LINE.saturating_sub(overhead).max(MIN_PAYLOAD)
+}
+
+/// The text, cut at a word boundary and marked with an ellipsis if it's too long.
+pub fn elide(text: &str, limit: usize) -> String {
+    if text.len() <= limit {
+        return text.to_string();
+    }
+
+    ellipsis(text, limit)
+}
+
+/// Breaks text into at most `max_lines` lines of at most `limit` bytes, eliding the rest.
+///
+/// With a single line, line breaks in the input are shown as `¶`.
+pub fn split(text: &str, limit: usize, max_lines: usize) -> Vec<String> {
+    if max_lines <= 1 {
+        return vec![elide(&cleanup_newlines(text), limit)];
+    }
+
+    let mut lines: Vec<String> = text
+        .lines()
+        .map(|line| line.replace(|c: char| c.is_control(), " "))
+        .filter(|line| !line.trim().is_empty())
+        .flat_map(|line| wrap(line.trim_end(), limit))
+        .collect();
+
+    if lines.len() > max_lines {
+        lines.truncate(max_lines);
+        let last = lines.pop().expect("max_lines > 1");
+        lines.push(ellipsis(&last, limit));
+    }
+
+    lines
+}
+
+/// Always ends in an ellipsis, even if the text would have fitted.
+fn ellipsis(text: &str, limit: usize) -> String {
+    let budget = limit.saturating_sub(ELLIPSIS.len());
+    let mut cut = floor_char_boundary(text, budget);
+    if cut < text.len() {
+        // prefer the last space, unless that'd throw away most of the line
+        if !text[cut..].starts_with(' ')
+            && let Some(space) = text[..cut].rfind(' ')
+            && space >= budget / 2
+        {
+            cut = space;
+        }
+    }
+
+    format!("{}{}", text[..cut].trim_end(), ELLIPSIS)
+}
+
+/// Greedily fills lines with whole words, only breaking words which are longer than a line.
+fn wrap(line: &str, limit: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut current = String::new();
+
+    for word in line.split(' ').filter(|word| !word.is_empty()) {
+        let needed = if current.is_empty() {
+            word.len()
+        } else {
+            current.len() + 1 + word.len()
+        };
+        if needed <= limit {
+            if !current.is_empty() {
+                current.push(' ');
+            }
+            current.push_str(word);
+            continue;
+        }
+
+        if !current.is_empty() {
+            lines.push(std::mem::take(&mut current));
+        }
+
+        let mut word = word;
+        while word.len() > limit {
+            let cut =
+                floor_char_boundary(word, limit).max(word.chars().next().map_or(0, char::len_utf8));
+            lines.push(word[..cut].to_string());
+            word = &word[cut..];
+        }
+        current.push_str(word);
+    }
+
+    if !current.is_empty() {
+        lines.push(current);
+    }
+
+    lines
+}
+
+fn floor_char_boundary(text: &str, index: usize) -> usize {
+    if index >= text.len() {
+        return text.len();
+    }
+    (0..=index)
+        .rev()
+        .find(|&i| text.is_char_boundary(i))
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::elide;
+    use super::payload_limit;
+    use super::split;
+
+    #[test]
+    fn limits() {
+        let mask = "unsnap!~unsnap@user/unsnap";
+        assert_eq!(464, payload_limit(mask, "#channel"));
+        assert_eq!(64, payload_limit(&"a".repeat(600), "#channel"));
+    }
+
+    #[test]
+    fn eliding() {
+        assert_eq!("short", elide("short", 10));
+        assert_eq!("hello…", elide("hello world", 10));
+        assert_eq!("abcdefg…", elide("abcdefghijklmnop", 10));
+        assert_eq!("ééé…", elide("éééééé", 10));
+        assert!(elide(&"word ".repeat(200), 400).len() <= 400);
+    }
+
+    #[test]
+    fn splitting() {
+        assert_eq!(vec!["a ¶ b"], split("a\nb", 100, 1));
+        assert_eq!(vec!["a", "b"], split("a\n\nb\n", 100, 3));
+        assert_eq!(
+            vec!["one two", "three four", "five"],
+            split("one two three four five", 10, 3)
+        );
+        assert_eq!(
+            vec!["one two", "three…"],
+            split("one two three four five", 10, 2)
+        );
+        assert_eq!(vec!["abcdef", "ghij"], split("abcdefghij", 6, 2));
+    }
+}
@@ -0,0 +1,334 @@
+//! `!tell`: messages left for people, delivered when they next speak or join.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use chrono::DateTime;
+use chrono::TimeDelta;
+use chrono::Utc;
+use rusqlite::OptionalExtension;
+use rusqlite::params;
+
+use crate::config;
+use crate::db::Db;
+use crate::format;
+
+pub const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS tells (
+        id INTEGER PRIMARY KEY,
+        network TEXT NOT NULL,
+        sender TEXT NOT NULL,
+        -- lower-cased, for quotas
+        sender_key TEXT NOT NULL,
+        -- lower-cased
+        recipient TEXT NOT NULL,
+        -- the recipient's services account, if we'd seen it, so a new nick still matches
+        recipient_account TEXT,
+        text TEXT NOT NULL,
+        -- unix seconds
+        at INTEGER NOT NULL,
+        -- unix seconds, when someone started passing it on
+        claimed INTEGER
+    );
+    CREATE INDEX IF NOT EXISTS tells_recipient ON tells (network, recipient);
+    CREATE INDEX IF NOT EXISTS tells_at ON tells (at);
+    -- the services account each nick was last seen using
+    CREATE TABLE IF NOT EXISTS accounts (
+        network TEXT NOT NULL,
+        nick TEXT NOT NULL,
+        account TEXT NOT NULL,
+        PRIMARY KEY (network, nick)
+    );
+";
+
+/// A message waiting for someone.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tell {
+    pub id: i64,
+    pub sender: String,
+    pub text: String,
+    pub at: DateTime<Utc>,
+}
+
+impl Tell {
+    /// What's said when it's passed on.
+    pub fn line(&self, now: DateTime<Utc>) -> String {
+        format!(
+            "{} asked me to tell you, {} ago: {}",
+            self.sender,
+            format::ago(now - self.at),
+            self.text
+        )
+    }
+}
+
+/// A claimed message which hasn't been sent or released by then was lost, e.g. in a crash.
+const CLAIM_TIMEOUT: TimeDelta = TimeDelta::minutes(10);
+
+pub struct Tells {
+    db: Arc<Db>,
+    config: config::Tell,
+}
+
+impl Tells {
+    pub fn new(db: Arc<Db>, config: &config::Tell) -> Tells {
+        Tells {
+            db,
+            config: config.clone(),
+        }
+    }
+
+    /// Remembers which account a nick is using, so messages for it can follow them.
+    pub fn noticed(&self, network: &str, nick: &str, account: Option<&str>) -> Result<()> {
+        let Some(account) = account else {
+            return Ok(());
+        };
+        let nick = nick.to_lowercase();
+        self.db.with(|db| {
+            // most of the time it's the same as ever, and reading doesn't wait for the disk
+            let known: Option<String> = db
+                .query_row(
+                    "SELECT account FROM accounts WHERE network = ?1 AND nick = ?2",
+                    params![network, nick],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            if known.as_deref() != Some(account) {
+                db.execute(
+                    "INSERT OR REPLACE INTO accounts (network, nick, account) VALUES (?1, ?2, ?3)",
+                    params![network, nick, account],
+                )?;
+            }
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    /// `!tell nick message`; the reply to the sender.
+    pub fn leave(
+        &self,
+        network: &str,
+        sender: &str,
+        sender_account: Option<&str>,
+        args: &str,
+        now: DateTime<Utc>,
+    ) -> Result<String> {
+        if !self.config.enabled {
+            return Ok("I'm not passing messages on.".to_string());
+        }
+        let Some((recipient, text)) = args.split_once(' ') else {
+            return Ok("Tell them what?".to_string());
+        };
+        let text = text.trim();
+        if text.is_empty() {
+            return Ok("Tell them what?".to_string());
+        }
+        if !is_nick(recipient) {
+            return Ok(format!("{} isn't a nick.", recipient));
+        }
+        if recipient.eq_ignore_ascii_case(sender) {
+            return Ok("You could just remember it.".to_string());
+        }
+
+        // an account is the same person under any nick
+        let sender_key = sender_account.unwrap_or(sender).to_lowercase();
+        let cutoff = self.cutoff(now).timestamp();
+        let waiting: usize = self.db.with(|db| {
+            db.execute("DELETE FROM tells WHERE at < ?1", params![cutoff])?;
+            db.query_row(
+                "SELECT COUNT(*) FROM tells WHERE network = ?1 AND sender_key = ?2",
+                params![network, sender_key],
+                |row| row.get(0),
+            )
+        })?;
+        if waiting >= self.config.max_per_sender {
+            return Ok(format!(
+                "You already have {} messages waiting; wait for some to be delivered.",
+                waiting
+            ));
+        }
+
+        self.db.with(|db| {
+            let account: Option<String> = db
+                .query_row(
+                    "SELECT account FROM accounts WHERE network = ?1 AND nick = ?2",
+                    params![network, recipient.to_lowercase()],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            db.execute(
+                "INSERT INTO tells (network, sender, sender_key, recipient, recipient_account, text, at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                params![
+                    network,
+                    sender,
+                    sender_key,
+                    recipient.to_lowercase(),
+                    account,
+                    text,
+                    now.timestamp()
+                ],
+            )
+        })?;
+        Ok(format!("I'll tell {} when I next see them.", recipient))
+    }
+
+    /// Messages waiting for someone, oldest first, for the caller to pass on; nobody else gets
+    /// them until they're `released`, and they stay until they're `done`.
+    pub fn claim(
+        &self,
+        network: &str,
+        nick: &str,
+        account: Option<&str>,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<Tell>> {
+        if !self.config.enabled {
+            return Ok(Vec::new());
+        }
+
+        let mut claimed: Vec<Tell> = self.db.with(|db| {
+            // one statement, so two deliveries at once can't both claim a message
+            let mut statement = db.prepare_cached(
+                "UPDATE tells SET claimed = ?5
+                 WHERE network = ?1 AND at >= ?4
+                   AND (claimed IS NULL OR claimed < ?6)
+                   -- once we know whose account it's for, only that account will do
+                   AND CASE WHEN recipient_account IS NOT NULL
+                            THEN lower(recipient_account) = lower(?3)
+                            ELSE recipient = ?2 OR recipient = lower(?3)
+                       END
+                 RETURNING id, sender, text, at",
+            )?;
+            statement
+                .query_map(
+                    params![
+                        network,
+                        nick.to_lowercase(),
+                        account,
+                        self.cutoff(now).timestamp(),
+                        now.timestamp(),
+                        (now - CLAIM_TIMEOUT).timestamp()
+                    ],
+                    |row| {
+                        Ok(Tell {
+                            id: row.get(0)?,
+                            sender: row.get(1)?,
+                            text: row.get(2)?,
+                            at: DateTime::from_timestamp(row.get(3)?, 0).unwrap_or(now),
+                        })
+                    },
+                )?
+                .collect()
+        })?;
+        // RETURNING doesn't promise any order
+        claimed.sort_by_key(|tell| (tell.at, tell.id));
+        Ok(claimed)
+    }
+
+    /// Forgets a message which has been passed on.
+    pub fn done(&self, tell: &Tell) -> Result<()> {
+        self.db
+            .with(|db| db.execute("DELETE FROM tells WHERE id = ?1", params![tell.id]))?;
+        Ok(())
+    }
+
+    /// Puts back a claimed message which couldn't be passed on, for next time.
+    pub fn release(&self, tell: &Tell) -> Result<()> {
+        self.db.with(|db| {
+            db.execute(
+                "UPDATE tells SET claimed = NULL WHERE id = ?1",
+                params![tell.id],
+            )
+        })?;
+        Ok(())
+    }
+
+    fn cutoff(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        now - TimeDelta::days(self.config.expiry_days as i64)
+    }
+}
+
+/// Could be someone's nick, rather than a channel, a list, or a mask.
+fn is_nick(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || "[]\\`_^{|}".contains(c))
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "[]\\`_^{|}-".contains(c))
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::Tells;
+    use crate::config;
+    use crate::db;
+
+    #[test]
+    fn telling() {
+        let tells = Tells::new(
+            db::scratch(),
+            &config::Tell {
+                max_per_sender: 2,
+                ..config::Tell::default()
+            },
+        );
+        let now = db::some_time();
+        let later = now + TimeDelta::hours(2);
+        let leave = |sender: &str, args: &str| tells.leave("net", sender, None, args, now).unwrap();
+        let claim = |network, nick, account, at| {
+            tells
+                .claim(network, nick, account, at)
+                .unwrap()
+                .iter()
+                .map(|tell| tell.line(at))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!("Tell them what?", leave("faux", "bob "));
+        for recipient in ["#chan", "a,b", "*!*@*", "9lives"] {
+            assert_eq!(
+                format!("{} isn't a nick.", recipient),
+                leave("faux", &format!("{} hi", recipient))
+            );
+        }
+
+        tells.noticed("net", "Bob", Some("bobby")).unwrap();
+        leave("faux", "bob hi there");
+        leave("faux", "alice hello");
+        assert!(leave("Faux", "alice again").starts_with("You already have 2"));
+
+        // renamed, but still logged in
+        let sending = tells.claim("net", "bob_", Some("bobby"), later).unwrap();
+        assert_eq!(
+            vec!["faux asked me to tell you, 2 hours ago: hi there"],
+            sending
+                .iter()
+                .map(|tell| tell.line(later))
+                .collect::<Vec<_>>()
+        );
+        // already being passed on, e.g. as they join and speak at once
+        assert!(claim("net", "bob", Some("bobby"), later).is_empty());
+        // not sent, so kept for next time
+        tells.release(&sending[0]).unwrap();
+        let sending = tells.claim("net", "bob", Some("bobby"), later).unwrap();
+        assert_eq!(1, sending.len());
+        tells.done(&sending[0]).unwrap();
+        assert!(claim("net", "bob_", Some("bobby"), later + TimeDelta::hours(1)).is_empty());
+
+        // someone else using the nick, logged in or not, doesn't get bobby's messages
+        leave("faux", "bob again");
+        assert!(claim("net", "bob", None, later).is_empty());
+        assert!(claim("net", "bob", Some("robert"), later).is_empty());
+
+        assert!(claim("other", "alice", None, later).is_empty());
+        // claimed, and never sent or released, as if we'd crashed
+        assert_eq!(1, claim("net", "alice", None, later).len());
+        assert!(claim("net", "alice", None, later + TimeDelta::minutes(1)).is_empty());
+        assert_eq!(
+            1,
+            claim("net", "alice", None, later + TimeDelta::hours(1)).len()
+        );
+        assert!(claim("net", "alice", None, now + TimeDelta::days(365)).is_empty());
+    }
+}
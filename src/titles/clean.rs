@@ -0,0 +1,187 @@
+use url::Url;
+
+use crate::config;
+
+/// Parameters which only exist to track who shared what, stripped from every url.
+/// A trailing `*` matches any parameter with that prefix.
+const GLOBAL: &[&str] = &[
+    "utm_*",
+    "fbclid",
+    "gclid",
+    "gclsrc",
+    "dclid",
+    "gbraid",
+    "wbraid",
+    "msclkid",
+    "yclid",
+    "twclid",
+    "ttclid",
+    "igshid",
+    "mc_cid",
+    "mc_eid",
+    "_hsenc",
+    "_hsmi",
+    "mkt_tok",
+    "oly_anon_id",
+    "oly_enc_id",
+    "vero_id",
+    "__s",
+];
+
+/// Parameters which are tracking on these sites (and their subdomains), but might not be elsewhere.
+const SITES: &[(&str, &[&str])] = &[
+    ("youtube.com", &["si", "feature", "pp"]),
+    ("youtu.be", &["si", "feature"]),
+    ("open.spotify.com", &["si", "context", "nd"]),
+    ("twitter.com", &["s", "t", "ref_src", "ref_url"]),
+    ("x.com", &["s", "t", "ref_src", "ref_url"]),
+    ("instagram.com", &["igsh", "img_index"]),
+    ("reddit.com", &["share_id", "ref", "ref_source"]),
+    (
+        "tiktok.com",
+        &["_r", "_t", "is_from_webapp", "sender_device"],
+    ),
+    ("facebook.com", &["mibextid", "__tn__", "__cft__[0]"]),
+    ("linkedin.com", &["trk", "trackingId", "lipi"]),
+    (
+        "amazon.com",
+        &["ref", "ref_", "pf_rd_*", "pd_rd_*", "content-id"],
+    ),
+    (
+        "amazon.co.uk",
+        &["ref", "ref_", "pf_rd_*", "pd_rd_*", "content-id"],
+    ),
+];
+
+pub struct Cleaner {
+    global: Vec<String>,
+    sites: Vec<(String, Vec<String>)>,
+}
+
+impl Cleaner {
+    pub fn new(config: &config::Clean) -> Cleaner {
+        let mut global: Vec<String> = GLOBAL.iter().map(|p| p.to_string()).collect();
+        global.extend(config.params.iter().cloned());
+
+        let mut sites: Vec<(String, Vec<String>)> = SITES
+            .iter()
+            .map(|(host, params)| {
+                (
+                    host.to_string(),
+                    params.iter().map(|p| p.to_string()).collect(),
+                )
+            })
+            .collect();
+        for (host, params) in &config.sites {
+            sites.push((host.to_ascii_lowercase(), params.clone()));
+        }
+
+        Cleaner { global, sites }
+    }
+
+    /// The url without its tracking parameters; unchanged if there were none, or it won't parse.
+    pub fn clean(&self, url: &str) -> String {
+        let mut parsed = match Url::parse(url) {
+            Ok(parsed) => parsed,
+            Err(_) => return url.to_string(),
+        };
+
+        let query = match parsed.query() {
+            Some(query) => query.to_string(),
+            None => return url.to_string(),
+        };
+
+        let host = parsed.host_str().unwrap_or("").to_string();
+        let site_params: Vec<&String> = self
+            .sites
+            .iter()
+            .filter(|(site, _)| host == *site || host.ends_with(&format!(".{}", site)))
+            .flat_map(|(_, params)| params)
+            .collect();
+
+        let is_tracking = |key: &str| {
+            self.global
+                .iter()
+                .chain(site_params.iter().copied())
+                .any(|pattern| matches(pattern, key))
+        };
+
+        let kept: Vec<&str> = query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .filter(|pair| !is_tracking(pair.split('=').next().unwrap_or(pair)))
+            .collect();
+
+        if kept.len() == query.split('&').filter(|pair| !pair.is_empty()).count() {
+            return url.to_string();
+        }
+
+        if kept.is_empty() {
+            parsed.set_query(None);
+        } else {
+            parsed.set_query(Some(&kept.join("&")));
+        }
+
+        parsed.to_string()
+    }
+}
+
+fn matches(pattern: &str, key: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => key.starts_with(prefix),
+        None => pattern == key,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cleaner;
+    use crate::config;
+
+    #[test]
+    fn cleaning() {
+        let cleaner = Cleaner::new(&config::Clean {
+            params: vec!["ponies".to_string()],
+            sites: maplit::hashmap! {
+                "example.com".to_string() => vec!["via".to_string()],
+            },
+            ..Default::default()
+        });
+
+        for (expected, input) in &[
+            ("https://example.com/", "https://example.com/"),
+            ("https://example.com/?a=b", "https://example.com/?a=b"),
+            (
+                "https://example.com/a?b=c&d=e",
+                "https://example.com/a?utm_source=x&b=c&fbclid=IwAR0&d=e&utm_medium=y",
+            ),
+            ("https://example.com/a", "https://example.com/a?gclid=abc"),
+            (
+                "https://example.com/a#frag",
+                "https://example.com/a?ponies=1#frag",
+            ),
+            (
+                "https://www.example.com/a?b=c",
+                "https://www.example.com/a?via=me&b=c",
+            ),
+            ("https://other.com/a?via=me", "https://other.com/a?via=me"),
+            (
+                "https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=42",
+                "https://www.youtube.com/watch?v=dQw4w9WgXcQ&si=Ab3dEfGhIjKlMnOp&t=42",
+            ),
+            (
+                "https://youtu.be/dQw4w9WgXcQ",
+                "https://youtu.be/dQw4w9WgXcQ?si=Ab3dEfGhIjKlMnOp",
+            ),
+            (
+                "https://open.spotify.com/track/6rqhFgbbKwnb9MLmUQDhG6",
+                "https://open.spotify.com/track/6rqhFgbbKwnb9MLmUQDhG6?si=1234567890abcdef",
+            ),
+            // 'si' is only tracking on some sites
+            ("https://example.org/?si=1", "https://example.org/?si=1"),
+            ("not a url?utm_source=x", "not a url?utm_source=x"),
+        ] {
+            assert_eq!(*expected, cleaner.clean(input), "cleaning {:?}", input);
+        }
+    }
+}
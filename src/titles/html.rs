@@ -13,7 +13,13 @@ lazy_static::lazy_static! {
 }
 
 pub async fn process(http: Client, url: &str) -> Result<String> {
+    Ok(fetch(http, url).await?.0)
+}
+
+/// The title, or a description of what's there instead, and where the url led after redirects.
+pub async fn fetch(http: Client, url: &str) -> Result<(String, String)> {
     let mut resp = http.get(url).send().await?;
+    let landed = resp.url().to_string();
     const PREVIEW_BYTES: usize = 64 * 4096;
 
     let content_length = content_length(&resp);
@@ -24,7 +30,9 @@ pub async fn process(http: Client, url: &str) -> Result<String> {
     let buf = &buf[..found];
 
     let missing = match parse_html(buf) {
-        Ok(ref title) if !strip_whitespace(title).is_empty() => return Ok(title.to_owned()),
+        Ok(ref title) if !strip_whitespace(title).is_empty() => {
+            return Ok((title.to_owned(), landed));
+        }
         Ok(_empty) => false,
         Err(e) => {
             info!("no title found for {:?}: {}", url, e);
@@ -34,10 +42,8 @@ pub async fn process(http: Client, url: &str) -> Result<String> {
 
     let len = if buf.len() < PREVIEW_BYTES {
         Some(buf.len() as f64)
-    } else if let Some(len) = content_length {
-        Some(len)
     } else {
-        None
+        content_length
     };
 
     let ret = if missing {
@@ -56,10 +62,10 @@ pub async fn process(http: Client, url: &str) -> Result<String> {
         ret.push_str(&format!(" Size: {}.", show_size(len)));
     }
 
-    Ok(ret)
+    Ok((ret, landed))
 }
 
-fn parse_html(buf: &[u8]) -> Result<String, &'static str> {
+pub fn parse_html(buf: &[u8]) -> Result<String, &'static str> {
     let title = match TITLE.captures_iter(buf).next() {
         Some(cap) => String::from_utf8_lossy(&cap[1]).to_string(),
         None => return Err("no regex match"),
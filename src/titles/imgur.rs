@@ -121,11 +121,11 @@ fn render_gallery(resp: Value) -> Result<String> {
 }
 
 fn preferred_link(image: &Value) -> Result<&str> {
-    Ok(image
+    image
         .get("mp4")
         .or_else(|| image.get("link"))
         .and_then(|s| s.as_str())
-        .ok_or(format_err!("no link on embedded image"))?)
+        .ok_or(format_err!("no link on embedded image"))
 }
 
 fn preferred_size(data: &Value) -> Option<f64> {
@@ -144,7 +144,6 @@ fn try_f64(data: &Value, key: &str) -> Option<f64> {
 
 #[cfg(test)]
 mod tests {
-    use serde_json;
     use serde_json::Value;
 
     const STRAIGHT_IMAGE: &str = r#"
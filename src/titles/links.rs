@@ -0,0 +1,160 @@
+use regex::Regex;
+use url::Url;
+
+use crate::format::FORMATTING;
+
+lazy_static::lazy_static! {
+    static ref CANDIDATE: Regex =
+        Regex::new(r#"(?i)(?:https?://|www\.)[^\s<>"\p{Cc}]+"#).unwrap();
+}
+
+/// Punctuation which usually belongs to the sentence, not the link, when it ends one.
+const TRAILING: &[char] = &['.', ',', ':', ';', '!', '?', '\'', '"', '*', '`'];
+
+/// Links in an irc line, in order, without duplicates; schemeless `www.` links gain `http://`.
+pub fn extract(line: &str) -> Vec<String> {
+    let line = FORMATTING.replace_all(line, " ");
+    let mut found = Vec::new();
+
+    for candidate in CANDIDATE.find_iter(&line) {
+        let preceding = line[..candidate.start()].chars().next_back();
+        let schemeless = !candidate.as_str().contains("://");
+
+        // e.g. an email address, or a subdomain which happens to contain "www."
+        if schemeless && preceding.is_some_and(|c| c.is_alphanumeric() || "./@-_".contains(c)) {
+            continue;
+        }
+
+        let trimmed = trim(candidate.as_str());
+        let link = if schemeless {
+            format!("http://{}", trimmed)
+        } else {
+            trimmed.to_string()
+        };
+
+        match Url::parse(&link) {
+            Ok(url) if url.host_str().is_some_and(|host| !host.is_empty()) => (),
+            _ => continue,
+        }
+
+        if !found.contains(&link) {
+            found.push(link);
+        }
+    }
+
+    found
+}
+
+fn trim(mut link: &str) -> &str {
+    loop {
+        let last = match link.chars().next_back() {
+            Some(last) => last,
+            None => return link,
+        };
+
+        let unbalanced = |open: char, close: char| {
+            last == close && link.matches(close).count() > link.matches(open).count()
+        };
+
+        if TRAILING.contains(&last)
+            || unbalanced('(', ')')
+            || unbalanced('[', ']')
+            || unbalanced('{', '}')
+        {
+            link = &link[..link.len() - last.len_utf8()];
+        } else {
+            return link;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::extract;
+
+    #[test]
+    fn table() {
+        for (input, expected) in &[
+            ("no links here", vec![]),
+            ("https://example.com", vec!["https://example.com"]),
+            (
+                "http://example.com/a?b=c#d",
+                vec!["http://example.com/a?b=c#d"],
+            ),
+            (
+                "look: https://example.com/a.",
+                vec!["https://example.com/a"],
+            ),
+            ("https://example.com/a, and", vec!["https://example.com/a"]),
+            (
+                "really?! https://example.com/a?!",
+                vec!["https://example.com/a"],
+            ),
+            ("'https://example.com/a'", vec!["https://example.com/a"]),
+            ("\"https://example.com/a\"", vec!["https://example.com/a"]),
+            ("<https://example.com/a>", vec!["https://example.com/a"]),
+            ("<https://example.com/a>.", vec!["https://example.com/a"]),
+            ("(see https://example.com/a)", vec!["https://example.com/a"]),
+            (
+                "https://en.wikipedia.org/wiki/Rust_(programming_language)",
+                vec!["https://en.wikipedia.org/wiki/Rust_(programming_language)"],
+            ),
+            (
+                "(https://en.wikipedia.org/wiki/Rust_(programming_language)).",
+                vec!["https://en.wikipedia.org/wiki/Rust_(programming_language)"],
+            ),
+            ("[https://example.com/a]", vec!["https://example.com/a"]),
+            (
+                "https://example.com/a[1] ok",
+                vec!["https://example.com/a[1]"],
+            ),
+            (
+                "\x01ACTION shares https://example.com/a\x01",
+                vec!["https://example.com/a"],
+            ),
+            (
+                "\x02https://example.com/a\x02",
+                vec!["https://example.com/a"],
+            ),
+            (
+                "\x0304,12https://example.com/a\x03 red",
+                vec!["https://example.com/a"],
+            ),
+            (
+                "\x1fhttps://example.com/a\x0f\x1dtext",
+                vec!["https://example.com/a"],
+            ),
+            (
+                "\x04ff0000https://example.com/a",
+                vec!["https://example.com/a"],
+            ),
+            ("www.example.com", vec!["http://www.example.com"]),
+            ("go to www.example.com/a.", vec!["http://www.example.com/a"]),
+            ("WWW.EXAMPLE.COM", vec!["http://WWW.EXAMPLE.COM"]),
+            ("me@www.example.com", vec![]),
+            ("foo.www.example.com", vec![]),
+            (
+                "https://www.example.com/a",
+                vec!["https://www.example.com/a"],
+            ),
+            ("https:// nothing", vec![]),
+            ("https://", vec![]),
+            (
+                "https://a.example/ and https://b.example/, then https://a.example/",
+                vec!["https://a.example/", "https://b.example/"],
+            ),
+            ("https://fenêt.re/ünï", vec!["https://fenêt.re/ünï"]),
+            ("HTTPS://EXAMPLE.COM/A", vec!["HTTPS://EXAMPLE.COM/A"]),
+        ] {
+            assert_eq!(
+                expected
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect::<Vec<String>>(),
+                extract(input),
+                "extracting from {:?}",
+                input
+            );
+        }
+    }
+}
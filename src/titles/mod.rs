@@ -1,20 +1,30 @@
+pub mod clean;
 mod html;
 mod imgur;
+pub mod links;
 mod reddit;
+pub mod rules;
+pub mod scripts;
 mod spotify;
 mod twitter;
 mod youtube;
 
+use std::fmt;
+use std::sync::Arc;
+
 use anyhow::Result;
 use regex::Regex;
 use reqwest::Client;
-use std::sync::Arc;
 use url::Url;
 
+use crate::format;
+use crate::split;
 use crate::webs::Context;
 
+/// Titles are shortened to at least this, before the cleaned link is dropped to make room.
+const MIN_TITLE: usize = 40;
+
 lazy_static::lazy_static! {
-    static ref URL: Regex = Regex::new("https?://[^ ]+").unwrap();
     static ref IMGUR_IMAGE: Regex =
         Regex::new(r"https?://(?:i\.)?imgur\.com/([a-zA-Z0-9]{5,9})\.(?:jpg|mp4|webm|png|gif)")
             .unwrap();
@@ -31,63 +41,195 @@ lazy_static::lazy_static! {
     .unwrap();
     static ref CHAINED_NEWLINES: Regex = Regex::new(r"¶(?:\s*¶)+").unwrap();
     static ref REPEATED_SPACE: Regex = Regex::new(r"\s{2,}").unwrap();
+    static ref NSFW: Regex = Regex::new(r"\bNSFW\b").unwrap();
+}
+
+/// A title for a link, ready to be fitted into a message.
+#[derive(Clone, Debug)]
+pub struct Title {
+    pub host: String,
+    pub title: String,
+    /// the link without its tracking junk, if that's worth showing
+    pub cleaned: Option<String>,
+    /// the link as it was posted
+    pub url: String,
+    pub found: Found,
+}
+
+/// What a link turned out to be; cached, and kept in the link log.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Found {
+    pub title: String,
+    /// which source titled it, e.g. `youtube` or `html`
+    pub provider: &'static str,
+    /// where the link led, after any redirects
+    pub url: String,
+}
+
+impl Title {
+    /// `[ host - title ]`, with the title shortened to fit in `limit` bytes.
+    ///
+    /// If `formatted`, the host is bold and NSFW markers are red.
+    pub fn render(&self, limit: usize, formatted: bool) -> String {
+        let host = if formatted {
+            format::bold(&self.host)
+        } else {
+            self.host.to_string()
+        };
+        let prefix = format!("[ {} - ", host);
+        let mut suffix = " ]".to_string();
+        if let Some(cleaned) = &self.cleaned {
+            // a long cleaned url isn't worth squeezing the title out for
+            if prefix.len() + suffix.len() + 1 + cleaned.len() + MIN_TITLE <= limit {
+                suffix.push(' ');
+                suffix.push_str(cleaned);
+            }
+        }
+
+        let markers = if formatted {
+            NSFW.find_iter(&self.title).count() * format::COLOUR_OVERHEAD
+        } else {
+            0
+        };
+
+        let room = limit.saturating_sub(prefix.len() + suffix.len() + markers);
+        let mut title = split::elide(&self.title, room);
+        if formatted {
+            title = NSFW
+                .replace_all(&title, |m: &regex::Captures| {
+                    format::colour(&m[0], format::RED)
+                })
+                .to_string();
+        }
+        format!("{}{}{}", prefix, title, suffix)
+    }
+}
+
+impl fmt::Display for Title {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.render(usize::MAX, false))
+    }
 }
 
-pub async fn titles_for(http: Client, context: Arc<Context>, line: &str) -> Result<Vec<String>> {
+pub async fn titles_for(http: Client, context: Arc<Context>, line: &str) -> Result<Vec<Title>> {
     let mut v = Vec::new();
-    for url in URL.find_iter(line) {
-        let http = http.clone();
-        let context = Arc::clone(&context);
-        if let Some(title) = title_for(http, context, url.as_str()).await? {
-            v.push(format!(
-                "[ {} - {} ]",
-                hostname(url.as_str()),
-                strip_whitespace(&title)
-            ));
+    for url in links::extract(line) {
+        let cleaned = context.titling().cleaner.clean(&url);
+        let title = match context.state.cached_title(&cleaned) {
+            Some(title) => title,
+            None => {
+                if !context.state.allow_fetch(&hostname(&cleaned)) {
+                    info!("too many recent fetches, not titling {:?}", cleaned);
+                    continue;
+                }
+                let title = title_for(http.clone(), Arc::clone(&context), &cleaned).await?;
+                context.state.cache_title(&cleaned, title.clone());
+                title
+            }
+        };
+
+        if let Some(found) = title {
+            let clean = &context.config.clean;
+            v.push(Title {
+                host: hostname(&cleaned),
+                title: strip_whitespace(&found.title),
+                cleaned: if clean.show_cleaned && url.len() >= cleaned.len() + clean.min_saving {
+                    Some(cleaned)
+                } else {
+                    None
+                },
+                url,
+                found,
+            });
         }
     }
 
     Ok(v)
 }
 
-async fn title_for(http: Client, context: Arc<Context>, url: &str) -> Result<Option<String>> {
+async fn title_for(http: Client, context: Arc<Context>, url: &str) -> Result<Option<Found>> {
+    let found = |provider, title| Found {
+        title,
+        provider,
+        url: url.to_string(),
+    };
+
     if let Some(m) = IMGUR_IMAGE.captures(url) {
         let id = &m[1];
-        return Ok(Some(imgur::image(http, context, id).await?));
+        let result = imgur::image(http, Arc::clone(&context), id).await;
+        context.state.record("imgur", &result);
+        return Ok(Some(found("imgur", result?)));
     }
 
     if let Some(m) = IMGUR_GALLERY.captures(url) {
         let id = &m[1];
-        return Ok(Some(imgur::gallery(http, context, id).await?));
+        let result = imgur::gallery(http, Arc::clone(&context), id).await;
+        context.state.record("imgur", &result);
+        return Ok(Some(found("imgur", result?)));
     }
 
     if let Some(m) = REDDIT_VIDEO.captures(url) {
         let id = &m[1];
-        return Ok(Some(reddit::video(http, id).await?));
+        let result = reddit::video(http, id).await;
+        context.state.record("reddit", &result);
+        return Ok(Some(found("reddit", result?)));
     }
 
     if let Some(m) = SPOTIFY_WHATEVER.captures(url) {
         let kind = &m[1];
         let id = &m[2];
-        return Ok(Some(spotify::anything(http, context, kind, id).await?));
+        let result = spotify::anything(http, Arc::clone(&context), kind, id).await;
+        context.state.record("spotify", &result);
+        return Ok(Some(found("spotify", result?)));
     }
 
     if let Some(m) = TWITTER_TWEET.captures(url) {
         let id = &m[1];
-        return Ok(Some(twitter::tweet(http, context, id).await?));
+        let result = twitter::tweet(http, Arc::clone(&context), id).await;
+        context.state.record("twitter", &result);
+        return Ok(Some(found("twitter", result?)));
     }
 
     if let Some(m) = YOUTUBE_VIDEO.captures(url) {
         let id = &m[1];
-        return Ok(Some(youtube::video(http, context, id).await?));
+        let result = youtube::video(http, Arc::clone(&context), id).await;
+        context.state.record("youtube", &result);
+        return Ok(Some(found("youtube", result?)));
     }
 
-    Ok(html::process(http, url)
-        .await
-        .map(|s| strip_whitespace(&s))
+    let titling = context.titling();
+
+    if let Some((rule, vars)) = titling.rules.find(url) {
+        let result = rule.apply(&http, url, vars).await;
+        context.state.record("rules", &result);
+        match result {
+            Ok(Some(title)) => return Ok(Some(found("rules", title))),
+            Ok(None) => info!("rule for {:?} found nothing in {:?}", rule.host, url),
+            Err(e) => info!("rule for {:?} failed on {:?}: {:?}", rule.host, url, e),
+        }
+    }
+
+    if !titling.scripts.is_empty() {
+        let result = scripts::Scripts::title(Arc::clone(&titling), url).await;
+        context.state.record("scripts", &result);
+        match result {
+            Ok(Some(title)) => return Ok(Some(found("scripts", title))),
+            Ok(None) => (),
+            Err(e) => info!("script failed on {:?}: {:?}", url, e),
+        }
+    }
+
+    let result = html::fetch(http, url).await;
+    context.state.record("html", &result);
+    Ok(result
+        .map(|(title, landed)| Found {
+            title: strip_whitespace(&title),
+            provider: "html",
+            url: landed,
+        })
         .map_err(|e| {
             info!("gave up processing url {:?}: {:?}", url, e);
-            return e;
+            e
         })
         .ok())
 }
@@ -147,6 +289,36 @@ mod tests {
         assert_eq!("xn--fent-ipa.re", hostname("https://fenêt.re/"));
     }
 
+    #[test]
+    fn rendering() {
+        use super::Found;
+        use super::Title;
+        let title = Title {
+            host: "example.com".to_string(),
+            title: "a very long title indeed".to_string(),
+            cleaned: Some("https://example.com/".to_string()),
+            url: String::new(),
+            found: Found::default(),
+        };
+        assert_eq!(
+            "[ example.com - a very long title indeed ] https://example.com/",
+            title.to_string()
+        );
+        assert_eq!("[ example.com - a very… ]", title.render(28, false));
+
+        let nsfw = Title {
+            host: "imgur.com".to_string(),
+            title: "image/png 2.0KiB NSFW ፤ cat".to_string(),
+            cleaned: None,
+            url: String::new(),
+            found: Found::default(),
+        };
+        assert_eq!(
+            "[ \x02imgur.com\x02 - image/png 2.0KiB \x0304NSFW\x03 ፤ cat ]",
+            nsfw.render(400, true)
+        );
+    }
+
     #[test]
     fn new_lines() {
         use super::cleanup_newlines;
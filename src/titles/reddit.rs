@@ -8,10 +8,7 @@ pub async fn video(http: Client, id: &str) -> Result<String> {
     let html = crate::titles::html::process(http.clone(), &base).await.ok();
 
     let mut buf = vec![0u8; 32 * 1024];
-    let mut resp = http
-        .get(&format!("{}DASHPlaylist.mpd", base))
-        .send()
-        .await?;
+    let mut resp = http.get(format!("{}DASHPlaylist.mpd", base)).send().await?;
     read_many(&mut resp, &mut buf).await?;
     let dash_playlist = String::from_utf8_lossy(&buf);
     Ok(match crate::content::dash::highest_stream(&dash_playlist) {
@@ -0,0 +1,381 @@
+use std::collections::HashMap;
+
+use anyhow::Context as _;
+use anyhow::Result;
+use anyhow::anyhow;
+use anyhow::bail;
+use anyhow::format_err;
+use percent_encoding::AsciiSet;
+use percent_encoding::NON_ALPHANUMERIC;
+use percent_encoding::utf8_percent_encode;
+use regex::Regex;
+use reqwest::Client;
+use scraper::Html;
+use scraper::Selector;
+use serde_json::Value;
+use url::Url;
+
+use super::strip_whitespace;
+use crate::config;
+use crate::webs::errors;
+use crate::webs::read_many;
+
+lazy_static::lazy_static! {
+    static ref TEMPLATE_VAR: Regex = Regex::new(r"\{(\w+)\}").unwrap();
+}
+
+const PREVIEW_BYTES: usize = 64 * 4096;
+
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+pub struct Rule {
+    pub host: String,
+    path: Option<Regex>,
+    fetch: Option<String>,
+    extract: Extract,
+    template: String,
+}
+
+enum Extract {
+    Css(Selector),
+    Meta(Selector),
+    Json(String),
+    Regex(Regex),
+}
+
+impl Rules {
+    pub fn new(config: &[config::Rule]) -> Result<Rules> {
+        let rules = config
+            .iter()
+            .enumerate()
+            .map(|(i, rule)| {
+                Rule::new(rule).with_context(|| format_err!("rule {} ({:?})", i + 1, rule.host))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Rules { rules })
+    }
+
+    /// The first rule matching the url, and the variables it makes available to templates.
+    pub fn find(&self, url: &str) -> Option<(&Rule, HashMap<String, String>)> {
+        let parsed = Url::parse(url).ok()?;
+        let host = parsed.host_str()?;
+        let path = match parsed.query() {
+            Some(query) => format!("{}?{}", parsed.path(), query),
+            None => parsed.path().to_string(),
+        };
+
+        self.rules.iter().find_map(|rule| {
+            let mut vars = rule.matches(host, &path)?;
+            vars.insert("url".to_string(), url.to_string());
+            vars.insert("host".to_string(), host.to_string());
+            vars.insert("path".to_string(), path.to_string());
+            Some((rule, vars))
+        })
+    }
+}
+
+impl Rule {
+    fn new(rule: &config::Rule) -> Result<Rule> {
+        let extract = match (&rule.css, &rule.meta, &rule.json, &rule.regex) {
+            (Some(css), None, None, None) => Extract::Css(selector(css)?),
+            (None, Some(meta), None, None) => {
+                if meta.contains('"') {
+                    bail!("invalid meta name: {:?}", meta);
+                }
+                Extract::Meta(selector(&format!(
+                    r#"meta[property="{0}"], meta[name="{0}"]"#,
+                    meta
+                ))?)
+            }
+            (None, None, Some(json), None) => {
+                if !json.is_empty() && !json.starts_with('/') {
+                    bail!("json pointers start with a '/': {:?}", json);
+                }
+                Extract::Json(json.to_string())
+            }
+            (None, None, None, Some(regex)) => Extract::Regex(Regex::new(regex)?),
+            _ => bail!("exactly one of css, meta, json or regex is required"),
+        };
+
+        Ok(Rule {
+            host: rule.host.to_ascii_lowercase(),
+            path: rule
+                .path
+                .as_ref()
+                .map(|path| Regex::new(path))
+                .transpose()?,
+            fetch: rule.fetch.clone(),
+            extract,
+            template: rule.template.to_string(),
+        })
+    }
+
+    fn matches(&self, host: &str, path: &str) -> Option<HashMap<String, String>> {
+        if host != self.host && !host.ends_with(&format!(".{}", self.host)) {
+            return None;
+        }
+
+        let mut vars = HashMap::new();
+
+        let path_re = match &self.path {
+            Some(path_re) => path_re,
+            None => return Some(vars),
+        };
+
+        let captures = path_re.captures(path)?;
+        for (i, name) in path_re.capture_names().enumerate() {
+            if let Some(value) = captures.get(i) {
+                vars.insert(i.to_string(), value.as_str().to_string());
+                if let Some(name) = name {
+                    vars.insert(name.to_string(), value.as_str().to_string());
+                }
+            }
+        }
+
+        Some(vars)
+    }
+
+    pub async fn apply(
+        &self,
+        http: &Client,
+        url: &str,
+        mut vars: HashMap<String, String>,
+    ) -> Result<Option<String>> {
+        let target = match &self.fetch {
+            Some(fetch) => render_url(fetch, &vars),
+            None => url.to_string(),
+        };
+
+        let mut resp = errors(http.get(&target).send().await?)?;
+        let mut buf = vec![0u8; PREVIEW_BYTES];
+        let found = read_many(&mut resp, &mut buf).await?;
+        let body = String::from_utf8_lossy(&buf[..found]);
+
+        let title = match self
+            .extract(&body)
+            .with_context(|| format_err!("extracting from {:?}", target))?
+        {
+            Some(title) => strip_whitespace(&title),
+            None => return Ok(None),
+        };
+
+        if title.is_empty() {
+            return Ok(None);
+        }
+
+        vars.insert("title".to_string(), title);
+        Ok(Some(render(&self.template, &vars)))
+    }
+
+    fn extract(&self, body: &str) -> Result<Option<String>> {
+        Ok(match &self.extract {
+            Extract::Css(selector) => Html::parse_document(body)
+                .select(selector)
+                .next()
+                .map(|element| element.text().collect()),
+            Extract::Meta(selector) => Html::parse_document(body)
+                .select(selector)
+                .find_map(|element| element.value().attr("content").map(String::from)),
+            Extract::Json(pointer) => {
+                let doc: Value = serde_json::from_str(body).context("rule expected json")?;
+                doc.pointer(pointer).and_then(|value| match value {
+                    Value::Null => None,
+                    Value::String(s) => Some(s.to_string()),
+                    other => Some(other.to_string()),
+                })
+            }
+            Extract::Regex(regex) => regex.captures(body).map(|captures| {
+                let found = captures
+                    .get(1)
+                    .or_else(|| captures.get(0))
+                    .expect("group 0 always exists")
+                    .as_str();
+                htmlescape::decode_html(found).unwrap_or_else(|_| found.to_string())
+            }),
+        })
+    }
+}
+
+fn selector(css: &str) -> Result<Selector> {
+    Selector::parse(css).map_err(|e| anyhow!("invalid css selector {:?}: {}", css, e))
+}
+
+/// Everything but RFC 3986's unreserved characters.
+const RESERVED: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Replaces `{name}` with the value from `vars`; unknown names become empty.
+fn render(template: &str, vars: &HashMap<String, String>) -> String {
+    fill(template, vars, str::to_string)
+}
+
+/// `render`, for a url: values are percent-encoded, so they can't change which path or query
+/// is fetched.
+fn render_url(template: &str, vars: &HashMap<String, String>) -> String {
+    fill(template, vars, |value| {
+        utf8_percent_encode(value, RESERVED).to_string()
+    })
+}
+
+fn fill(template: &str, vars: &HashMap<String, String>, escape: impl Fn(&str) -> String) -> String {
+    TEMPLATE_VAR
+        .replace_all(template, |captures: &regex::Captures| {
+            vars.get(&captures[1])
+                .map(|value| escape(value))
+                .unwrap_or_default()
+        })
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rules;
+    use crate::config;
+
+    fn rule(host: &str) -> config::Rule {
+        config::Rule {
+            host: host.to_string(),
+            path: None,
+            fetch: None,
+            css: None,
+            meta: None,
+            json: None,
+            regex: None,
+            template: "{title}".to_string(),
+        }
+    }
+
+    #[test]
+    fn matching() {
+        let rules = Rules::new(&[
+            config::Rule {
+                path: Some(r"^/items/(?P<id>\d+)".to_string()),
+                fetch: Some("https://api.example.com/v1/{id}.json".to_string()),
+                json: Some("/title".to_string()),
+                ..rule("Example.com")
+            },
+            config::Rule {
+                meta: Some("og:title".to_string()),
+                ..rule("bbc.co.uk")
+            },
+        ])
+        .unwrap();
+
+        let (rule, vars) = rules.find("https://www.example.com/items/123?x=y").unwrap();
+        assert_eq!("example.com", rule.host);
+        assert_eq!("123", vars["id"]);
+        assert_eq!("123", vars["1"]);
+        assert_eq!("/items/123?x=y", vars["path"]);
+        assert_eq!(
+            "https://api.example.com/v1/123.json",
+            super::render_url(rule.fetch.as_ref().unwrap(), &vars)
+        );
+
+        assert!(rules.find("https://example.com/other").is_none());
+        assert!(rules.find("https://notexample.com/items/123").is_none());
+        assert_eq!(
+            "bbc.co.uk",
+            rules.find("http://www.bbc.co.uk/news").unwrap().0.host
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(Rules::new(&[rule("example.com")]).is_err());
+        assert!(
+            Rules::new(&[config::Rule {
+                css: Some("h1".to_string()),
+                regex: Some("a".to_string()),
+                ..rule("example.com")
+            }])
+            .is_err()
+        );
+        assert!(
+            Rules::new(&[config::Rule {
+                css: Some("h1[".to_string()),
+                ..rule("example.com")
+            }])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn extraction() {
+        let html = include_str!("../../tests/bbc.html");
+
+        let meta = Rules::new(&[config::Rule {
+            meta: Some("og:title".to_string()),
+            ..rule("bbc.co.uk")
+        }])
+        .unwrap();
+        assert_eq!(
+            Some("Queen wants Charles to lead Commonwealth".to_string()),
+            meta.rules[0].extract(html).unwrap()
+        );
+
+        let css = Rules::new(&[config::Rule {
+            css: Some("head > title".to_string()),
+            ..rule("bbc.co.uk")
+        }])
+        .unwrap();
+        assert_eq!(
+            Some(
+                "Commonwealth meeting: Queen hopes Prince Charles will succeed her - BBC News"
+                    .to_string()
+            ),
+            css.rules[0].extract(html).unwrap()
+        );
+
+        let json = Rules::new(&[config::Rule {
+            json: Some("/data/0/name".to_string()),
+            ..rule("example.com")
+        }])
+        .unwrap();
+        assert_eq!(
+            Some("ponies".to_string()),
+            json.rules[0]
+                .extract(r#"{"data": [{"name": "ponies"}]}"#)
+                .unwrap()
+        );
+        assert_eq!(None, json.rules[0].extract(r#"{"data": []}"#).unwrap());
+
+        let regex = Rules::new(&[config::Rule {
+            regex: Some(r"<h2>([^<]+)</h2>".to_string()),
+            ..rule("example.com")
+        }])
+        .unwrap();
+        assert_eq!(
+            Some("'ponies'".to_string()),
+            regex.rules[0]
+                .extract("<h1>foo</h1><h2>&#x27;ponies&#x27;</h2>")
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn templates() {
+        let vars = maplit::hashmap! {
+            "title".to_string() => "ponies".to_string(),
+            "1".to_string() => "42".to_string(),
+        };
+        assert_eq!(
+            "#42: ponies ()",
+            super::render("#{1}: {title} ({missing})", &vars)
+        );
+
+        // a capture can't add to the path or query that's fetched
+        let vars = maplit::hashmap! {
+            "id".to_string() => "1/../admin?x=1&y=2#z 100%".to_string(),
+        };
+        assert_eq!(
+            "https://api.example.com/items/1%2F..%2Fadmin%3Fx%3D1%26y%3D2%23z%20100%25?k=v",
+            super::render_url("https://api.example.com/items/{id}?k=v", &vars)
+        );
+        assert_eq!("1/../admin?x=1&y=2#z 100%", super::render("{id}", &vars));
+    }
+}
@@ -0,0 +1,254 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context as _;
+use anyhow::Result;
+use anyhow::anyhow;
+use anyhow::format_err;
+use reqwest::Client;
+use rhai::AST;
+use rhai::Dynamic;
+use rhai::Engine;
+use rhai::EvalAltResult;
+use rhai::Scope;
+use scraper::Html;
+use scraper::Selector;
+use serde_json::Value;
+use tokio::runtime::Handle;
+
+use super::youtube::major_duration_unit;
+use crate::webs::Titling;
+use crate::webs::errors;
+use crate::webs::read_many;
+
+const PREVIEW_BYTES: usize = 64 * 4096;
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Title providers written in rhai, each defining `matches(url)` and `title(url)`.
+pub struct Scripts {
+    engine: Engine,
+    scripts: Vec<Script>,
+}
+
+struct Script {
+    name: String,
+    ast: AST,
+}
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+impl Scripts {
+    pub fn load(http: &Client, dir: Option<&Path>) -> Result<Scripts> {
+        let dir = match dir {
+            Some(dir) => dir,
+            None => return Scripts::from_sources(http, Vec::new()),
+        };
+
+        let mut paths = fs::read_dir(dir)
+            .with_context(|| format_err!("reading scripts dir {:?}", dir))?
+            .map(|entry| Ok(entry?.path()))
+            .collect::<Result<Vec<_>>>()?;
+        paths.retain(|path| path.extension().is_some_and(|ext| ext == "rhai"));
+        paths.sort();
+
+        let mut sources = Vec::with_capacity(paths.len());
+        for path in paths {
+            let source =
+                fs::read_to_string(&path).with_context(|| format_err!("reading {:?}", path))?;
+            sources.push((path.display().to_string(), source));
+        }
+
+        Scripts::from_sources(http, sources)
+    }
+
+    fn from_sources(http: &Client, sources: Vec<(String, String)>) -> Result<Scripts> {
+        let engine = engine(http.clone());
+        let scripts = sources
+            .into_iter()
+            .map(|(name, source)| {
+                let ast = engine
+                    .compile(&source)
+                    .map_err(|e| anyhow!("compiling {}: {}", name, e))?;
+                for required in ["matches", "title"] {
+                    if !ast.iter_functions().any(|f| f.name == required) {
+                        return Err(anyhow!("{} does not define {}(url)", name, required));
+                    }
+                }
+                info!("loaded title script {}", name);
+                Ok(Script { name, ast })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Scripts { engine, scripts })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scripts.is_empty()
+    }
+
+    /// Runs the first script that claims the url. Scripts block, so this happens off the runtime.
+    pub async fn title(titling: Arc<Titling>, url: &str) -> Result<Option<String>> {
+        let url = url.to_string();
+        tokio::task::spawn_blocking(move || titling.scripts.title_blocking(&url)).await?
+    }
+
+    fn title_blocking(&self, url: &str) -> Result<Option<String>> {
+        for script in &self.scripts {
+            let matches: bool = self
+                .engine
+                .call_fn(
+                    &mut Scope::new(),
+                    &script.ast,
+                    "matches",
+                    (url.to_string(),),
+                )
+                .map_err(|e| anyhow!("{} matches(): {}", script.name, e))?;
+
+            if !matches {
+                continue;
+            }
+
+            let title: Dynamic = self
+                .engine
+                .call_fn(&mut Scope::new(), &script.ast, "title", (url.to_string(),))
+                .map_err(|e| anyhow!("{} title(): {}", script.name, e))?;
+
+            return Ok(if title.is_unit() {
+                None
+            } else {
+                Some(title.to_string())
+            });
+        }
+
+        Ok(None)
+    }
+}
+
+fn engine(http: Client) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(1_000_000);
+    engine.set_max_string_size(PREVIEW_BYTES);
+    engine.set_max_expr_depths(64, 32);
+
+    let fetch_http = http.clone();
+    engine.register_fn("fetch", move |url: &str| -> ScriptResult<String> {
+        fetch(&fetch_http, url)
+    });
+    engine.register_fn("fetch_json", move |url: &str| -> ScriptResult<Dynamic> {
+        parse_json(&fetch(&http, url)?)
+    });
+    engine.register_fn("parse_json", |text: &str| parse_json(text));
+    engine.register_fn("html_title", |html: &str| -> Dynamic {
+        super::html::parse_html(html.as_bytes())
+            .map(Dynamic::from)
+            .unwrap_or(Dynamic::UNIT)
+    });
+    engine.register_fn(
+        "html_select",
+        |html: &str, css: &str| -> ScriptResult<Dynamic> {
+            let selector = selector(css)?;
+            Ok(Html::parse_document(html)
+                .select(&selector)
+                .next()
+                .map(|element| Dynamic::from(element.text().collect::<String>()))
+                .unwrap_or(Dynamic::UNIT))
+        },
+    );
+    engine.register_fn(
+        "html_meta",
+        |html: &str, name: &str| -> ScriptResult<Dynamic> {
+            let selector = selector(&format!(
+                r#"meta[property="{0}"], meta[name="{0}"]"#,
+                name.replace('"', "")
+            ))?;
+            Ok(Html::parse_document(html)
+                .select(&selector)
+                .find_map(|element| element.value().attr("content"))
+                .map(|content| Dynamic::from(content.to_string()))
+                .unwrap_or(Dynamic::UNIT))
+        },
+    );
+    engine.register_fn("show_size", super::show_size);
+    engine.register_fn("show_size", |val: i64| super::show_size(val as f64));
+    engine.register_fn("duration", |secs: i64| {
+        major_duration_unit(&Duration::from_secs(secs.max(0) as u64))
+    });
+    engine.register_fn("cleanup_newlines", |text: &str| {
+        super::cleanup_newlines(text)
+    });
+
+    engine
+}
+
+fn fetch(http: &Client, url: &str) -> ScriptResult<String> {
+    Handle::current()
+        .block_on(async {
+            let mut resp = errors(http.get(url).timeout(FETCH_TIMEOUT).send().await?)?;
+            let mut buf = vec![0u8; PREVIEW_BYTES];
+            let found = read_many(&mut resp, &mut buf).await?;
+            Ok::<_, anyhow::Error>(String::from_utf8_lossy(&buf[..found]).to_string())
+        })
+        .map_err(|e| format!("fetching {:?}: {}", url, e).into())
+}
+
+fn parse_json(text: &str) -> ScriptResult<Dynamic> {
+    let value: Value = serde_json::from_str(text).map_err(|e| format!("bad json: {}", e))?;
+    rhai::serde::to_dynamic(value)
+}
+
+fn selector(css: &str) -> ScriptResult<Selector> {
+    Selector::parse(css).map_err(|e| format!("invalid css selector {:?}: {}", css, e).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Scripts;
+
+    #[test]
+    fn pure_helpers() {
+        let scripts = Scripts::from_sources(
+            &reqwest::Client::new(),
+            vec![(
+                "sizes.rhai".to_string(),
+                r#"
+                    fn matches(url) { url.starts_with("https://sizes.example/") }
+                    fn title(url) {
+                        let doc = parse_json(`{"bytes": 12828, "secs": 190, "name": "ponies"}`);
+                        if url.ends_with("/none") { return (); }
+                        `${doc.name} ${show_size(doc.bytes)} ${duration(doc.secs)}`
+                    }
+                "#
+                .to_string(),
+            )],
+        )
+        .unwrap();
+
+        assert_eq!(
+            Some("ponies 12.5KiB 3m".to_string()),
+            scripts.title_blocking("https://sizes.example/a").unwrap()
+        );
+        assert_eq!(
+            None,
+            scripts
+                .title_blocking("https://sizes.example/none")
+                .unwrap()
+        );
+        assert_eq!(
+            None,
+            scripts.title_blocking("https://other.example/").unwrap()
+        );
+    }
+
+    #[test]
+    fn required_functions() {
+        assert!(
+            Scripts::from_sources(
+                &reqwest::Client::new(),
+                vec![("bad.rhai".to_string(), "fn title(url) { url }".to_string())],
+            )
+            .is_err()
+        );
+    }
+}
@@ -63,20 +63,12 @@ struct SpotifyTrack {
     duration_ms: u64,
     name: String,
     preview_url: Option<String>,
-
-    popularity: f64,
-
-    disc_number: u64,
-    track_number: u64,
 }
 
 #[derive(Deserialize)]
 struct SpotifyAlbum {
-    artists: Vec<SpotifyArtist>,
-    name: String,
     // 2020-05-22
     release_date: String,
-    total_tracks: u64,
 }
 
 #[derive(Deserialize)]
@@ -43,8 +43,6 @@ fn render_tweet(resp: Value) -> Result<String> {
 
 #[cfg(test)]
 mod tests {
-    use serde_json;
-
     #[test]
     fn doc_sample() {
         assert_eq!(
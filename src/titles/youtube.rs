@@ -55,9 +55,9 @@ fn render_video(resp: Value) -> Result<String> {
 }
 
 fn string(value: Option<&Value>) -> Result<&str> {
-    Ok(value
+    value
         .and_then(|v| v.as_str())
-        .ok_or(anyhow!("expected a string"))?)
+        .ok_or(anyhow!("expected a string"))
 }
 
 pub fn major_duration_unit(duration: &Duration) -> String {
@@ -75,8 +75,6 @@ pub fn major_duration_unit(duration: &Duration) -> String {
 
 #[cfg(test)]
 mod tests {
-    use serde_json;
-
     #[test]
     fn aiweechoo() {
         assert_eq!(
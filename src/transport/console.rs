@@ -0,0 +1,145 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use tokio::io::AsyncBufReadExt;
+use tokio::io::BufReader;
+use tokio::io::Lines;
+use tokio::io::Stdin;
+
+use super::Event;
+use super::Message;
+use super::Outbox;
+use super::Reply;
+use super::Transport;
+use crate::config::ChannelSettings;
+use crate::format;
+use crate::split;
+
+const NICK: &str = "console";
+const CHANNEL: &str = "#console";
+
+/// As if we were on a real network, so replies are split the same way.
+const HOSTMASK: &str = "unsnap!~unsnap@localhost";
+
+/// Lines from stdin are messages to a channel, replies go to stdout.
+///
+/// `/me text` sends an action, and `/query text` a private message.
+pub struct Console {
+    lines: Lines<BufReader<Stdin>>,
+}
+
+struct Stdout;
+
+impl Console {
+    pub fn new() -> Console {
+        Console {
+            lines: BufReader::new(tokio::io::stdin()).lines(),
+        }
+    }
+}
+
+impl Transport for Console {
+    fn name(&self) -> &str {
+        "console"
+    }
+
+    fn nick(&self) -> String {
+        HOSTMASK.split('!').next().unwrap_or(HOSTMASK).to_string()
+    }
+
+    fn channel_settings(&self, _channel: &str) -> ChannelSettings {
+        ChannelSettings::default()
+    }
+
+    fn outbox(&self) -> Arc<dyn Outbox> {
+        Arc::new(Stdout)
+    }
+
+    async fn next(&mut self) -> Option<Event> {
+        loop {
+            let line = match self.lines.next_line().await {
+                Ok(Some(line)) => line,
+                Ok(None) => return None,
+                Err(e) => {
+                    warn!("reading stdin: {:?}", e);
+                    return None;
+                }
+            };
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            return Some(Event::Message(parse(&line)));
+        }
+    }
+}
+
+fn parse(line: &str) -> Message {
+    let (text, private, action) = if let Some(text) = line.strip_prefix("/me ") {
+        (text, false, true)
+    } else if let Some(text) = line.strip_prefix("/query ") {
+        (text, true, false)
+    } else {
+        (line, false, false)
+    };
+
+    Message {
+        source: NICK.to_string(),
+        target: if private { "unsnap" } else { CHANNEL }.to_string(),
+        text: text.to_string(),
+        private,
+        action,
+        id: None,
+        account: None,
+        hostmask: None,
+        time: None,
+    }
+}
+
+impl Outbox for Stdout {
+    fn send(&self, reply: Reply) -> Result<()> {
+        let kind = if reply.notice { "notice " } else { "" };
+        println!("-> {}{}: {}", kind, reply.target, format::strip(reply.text));
+        Ok(())
+    }
+
+    fn max_payload(&self, target: &str) -> usize {
+        split::payload_limit(HOSTMASK, target)
+    }
+
+    fn join(&self, channel: &str) -> Result<()> {
+        println!("-> join {}", channel);
+        Ok(())
+    }
+
+    fn part(&self, channel: &str, reason: &str) -> Result<()> {
+        println!("-> part {}: {}", channel, reason);
+        Ok(())
+    }
+
+    fn quit(&self, reason: &str) -> Result<()> {
+        println!("-> quit: {}", reason);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+
+    #[test]
+    fn commands() {
+        let plain = parse("hello https://example.com/");
+        assert_eq!("#console", plain.target);
+        assert!(!plain.private && !plain.action);
+
+        let action = parse("/me waves");
+        assert_eq!("waves", action.text);
+        assert!(action.action);
+
+        let private = parse("/query !qalc 1+1");
+        assert_eq!("!qalc 1+1", private.text);
+        assert!(private.private);
+    }
+}
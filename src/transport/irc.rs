@@ -0,0 +1,929 @@
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Context as _;
+use anyhow::Result;
+use anyhow::bail;
+use anyhow::format_err;
+use chrono::DateTime;
+use chrono::Utc;
+use futures::prelude::*;
+use irc::client::ClientStream;
+use irc::client::Sender;
+use irc::client::prelude as ic;
+use irc::proto::ChannelMode;
+use irc::proto::Mode;
+use irc::proto::Prefix;
+use irc::proto::message::Tag;
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+use tempfile::NamedTempFile;
+
+use super::Activity;
+use super::Doing;
+use super::Event;
+use super::Message;
+use super::Outbox;
+use super::Reply;
+use super::Transport;
+use crate::admin;
+use crate::backoff::Backoff;
+use crate::caps;
+use crate::config;
+use crate::config::ChannelSettings;
+use crate::config::Invites;
+use crate::ctcp;
+use crate::ctcp::Ctcp;
+use crate::format;
+use crate::split;
+
+/// Connections which lasted this long aren't part of a run of failures.
+const STABLE: Duration = Duration::from_secs(120);
+
+/// One network, connected to each of its servers in turn, forever, rejoining the configured
+/// channels, and those joined or parted since.
+pub struct Irc {
+    network: config::Network,
+    ctcp: config::Ctcp,
+    admin: config::Admin,
+    backoff: Backoff,
+    attempts: usize,
+    /// the channels to be in on the next connection
+    channels: Channels,
+    /// where the runtime changes to `channels` are kept between runs, if anywhere
+    channels_path: Option<PathBuf>,
+    /// when we've been kicked from each channel, recently
+    kicks: HashMap<String, Vec<Instant>>,
+    connection: Option<Connection>,
+    outbox: Arc<IrcOutbox>,
+}
+
+struct Connection {
+    client: ic::Client,
+    stream: ClientStream,
+    started: Instant,
+    caps: caps::Negotiation,
+    members: Members,
+}
+
+#[derive(Default)]
+struct IrcOutbox {
+    sender: Mutex<Option<Sender>>,
+    /// `nick!user@host`, as the server shows us to others; a pessimistic guess until we see it
+    hostmask: Mutex<String>,
+    /// IRCv3 capabilities enabled on this connection
+    caps: Mutex<HashSet<String>>,
+    /// channels which are +c, so won't accept colours or formatting, in lower case
+    colourless: Mutex<HashSet<String>>,
+    /// labels of messages sent, but not yet confirmed by the server, with their targets
+    pending: Mutex<HashMap<String, (String, Instant)>>,
+    next_label: AtomicU64,
+    /// we've said goodbye, so shouldn't reconnect
+    quitting: AtomicBool,
+}
+
+/// With labeled-response, messages not acknowledged in this time are probably lost.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The longest hostname a server will show, for guessing before we know our real one.
+const MAX_HOST: usize = 63;
+
+impl Irc {
+    pub fn new(network: config::Network, config: &config::Config) -> Irc {
+        let channels_path = config
+            .data_dir
+            .as_ref()
+            .map(|dir| dir.join(format!("channels-{}.json", file_safe(&network.name))));
+
+        let saved = match &channels_path {
+            Some(path) => load_channels(path).unwrap_or_else(|e| {
+                warn!("{}: not restoring channels: {:?}", network.name, e);
+                Channels::default()
+            }),
+            None => Channels::default(),
+        };
+        let channels = saved.on_top_of(&network.server.channels);
+
+        Irc {
+            network,
+            ctcp: config.ctcp.clone(),
+            admin: config.admin.clone(),
+            backoff: Backoff::new(Duration::from_secs(2), Duration::from_secs(300)),
+            attempts: 0,
+            channels,
+            channels_path,
+            kicks: HashMap::new(),
+            connection: None,
+            outbox: Arc::default(),
+        }
+    }
+
+    async fn connect(&mut self) -> Result<()> {
+        let server = &self.network.server;
+        let hostnames: Vec<&String> = std::iter::once(&server.hostname)
+            .chain(&server.alternate_hostnames)
+            .collect();
+        let hostname = hostnames[self.attempts % hostnames.len()];
+        self.attempts += 1;
+
+        let irc_config = ic::Config {
+            nickname: Some(server.nick.to_string()),
+            server: Some(hostname.to_string()),
+            port: Some(server.port),
+            username: server.user.clone(),
+            realname: server.real_name.clone(),
+            channels: self.channels.current(),
+            password: server.password.clone(),
+            // with sasl, we're already identified by the time nickserv would be asked
+            nick_password: match server.sasl {
+                Some(_) => None,
+                None => server.nick_password.clone(),
+            },
+
+            use_tls: Some(server.use_tls),
+            dangerously_accept_invalid_certs: Some(server.dangerously_accept_invalid_certs),
+            client_cert_path: server.client_cert_path.clone(),
+            client_cert_pass: server.client_cert_pass.clone(),
+
+            // freenode takes over 10s to warm up, including hostname verification failure
+            ping_timeout: Some(20),
+            ..Default::default()
+        };
+
+        info!("{}: connecting to {:?}", self.network.name, hostname);
+        let mut client = ic::Client::from_config(irc_config).await?;
+
+        caps::identify(&client, server)?;
+
+        let stream = client.stream()?;
+        self.outbox.replace(Some(client.sender()));
+        self.outbox.set_hostmask(format!(
+            "{}!~{}@{}",
+            server.nick,
+            server.user.as_ref().unwrap_or(&server.nick),
+            "x".repeat(MAX_HOST)
+        ));
+        self.connection = Some(Connection {
+            client,
+            stream,
+            started: Instant::now(),
+            caps: caps::Negotiation::default(),
+            members: Members::default(),
+        });
+
+        Ok(())
+    }
+
+    /// Lets the QUIT go out, and waits for the server to close the connection.
+    async fn hang_up(&mut self) {
+        if let Some(mut connection) = self.connection.take() {
+            while let Some(Ok(_)) = connection.stream.next().await {}
+        }
+        self.outbox.replace(None);
+    }
+
+    async fn reconnect_later(&mut self) {
+        self.outbox.replace(None);
+        if let Some(connection) = self.connection.take()
+            && connection.started.elapsed() > STABLE
+        {
+            self.backoff.reset();
+        }
+
+        if self.outbox.quitting.load(Ordering::Relaxed) {
+            return;
+        }
+
+        let delay = self.backoff.next_delay();
+        info!("{}: reconnecting in {:?}", self.network.name, delay);
+        tokio::time::sleep(delay).await;
+    }
+
+    /// Deals with the protocol-level parts of a message, and hands on the rest.
+    fn process(&mut self, message: &ic::Message) -> Result<Option<Event>> {
+        info!("{}: <- {:?}", self.network.name, message);
+
+        let connection = self
+            .connection
+            .as_mut()
+            .expect("processing while connected");
+        let client = &connection.client;
+        let shared = connection.members.track(client.current_nickname(), message);
+
+        let enabled = connection
+            .caps
+            .handle(client, &self.network.server, message)?;
+        self.outbox.enable(enabled);
+
+        if let Some(label) = caps::tag(message, "label") {
+            self.outbox.delivered(label);
+        }
+        self.outbox.check_deliveries(&self.network.name);
+
+        if track_channels(client, message, &mut self.channels)
+            && let Some(path) = &self.channels_path
+            && let Err(e) = save_channels(path, &self.channels)
+        {
+            warn!("{}: saving channels: {:?}", self.network.name, e);
+        }
+        track_modes(client, message, &self.outbox)?;
+
+        match message.command {
+            ic::Command::KICK(ref chan, ref victim, ref reason)
+                if victim == client.current_nickname() =>
+            {
+                warn!("{}: kicked from {}: {:?}", self.network.name, chan, reason);
+                if let Some(delay) = rejoin_delay(&self.network.server, &mut self.kicks, chan) {
+                    info!("{}: rejoining {} in {:?}", self.network.name, chan, delay);
+                    let sender = client.sender();
+                    let chan = chan.to_string();
+                    tokio::spawn(async move {
+                        tokio::time::sleep(delay).await;
+                        if let Err(e) = sender.send_join(&chan) {
+                            warn!("rejoining {}: {:?}", chan, e);
+                        }
+                    });
+                }
+            }
+            ic::Command::INVITE(ref nick, ref chan) if nick == client.current_nickname() => {
+                let from = message.prefix.as_ref().map(|prefix| prefix.to_string());
+                let allowed = match self.network.server.invites {
+                    Invites::Never => false,
+                    Invites::Admins => admin::is_operator(
+                        &self.admin,
+                        caps::tag(message, "account"),
+                        from.as_deref(),
+                    ),
+                    Invites::All => true,
+                };
+                info!(
+                    "{}: invited to {} by {:?}, accepting: {}",
+                    self.network.name, chan, from, allowed
+                );
+                if allowed {
+                    client.send_join(chan)?;
+                }
+            }
+            _ => (),
+        }
+
+        // e.g. our own JOINs tell us how we appear, after any cloak or ident changes
+        if message.source_nickname() == Some(client.current_nickname())
+            && let Some(prefix @ Prefix::Nickname(_, user, host)) = &message.prefix
+            && !user.is_empty()
+            && !host.is_empty()
+        {
+            self.outbox.set_hostmask(prefix.to_string());
+        }
+
+        let nick = match message.source_nickname() {
+            Some(nick) => nick,
+            None => return Ok(None),
+        };
+
+        let (dest, msg) = match message.command {
+            ic::Command::PRIVMSG(ref dest, ref msg) => (dest, msg),
+            _ => {
+                let us = client.current_nickname();
+                return Ok(activity(us, nick, message, shared).map(Event::Activity));
+            }
+        };
+
+        // with echo-message, the server tells us about everything we've said
+        if nick == client.current_nickname() {
+            return Ok(None);
+        }
+
+        let (text, action) = match ctcp::parse(msg) {
+            None => (msg.as_str(), false),
+            Some(Ctcp::Action(action)) => (action, true),
+            Some(Ctcp::Query(command, args)) => {
+                if let Some(reply) = ctcp::reply(&self.ctcp, command, args)
+                    && let Err(e) = client.send_notice(nick, ctcp::quote(&reply))
+                {
+                    warn!("{}: ctcp reply to {:?}: {:?}", self.network.name, nick, e);
+                }
+                return Ok(None);
+            }
+        };
+
+        Ok(Some(Event::Message(Message {
+            source: nick.to_string(),
+            target: dest.to_string(),
+            text: text.to_string(),
+            private: !is_channel(dest),
+            action,
+            id: caps::tag(message, "msgid").map(str::to_string),
+            account: caps::tag(message, "account").map(str::to_string),
+            hostmask: message.prefix.as_ref().map(|prefix| prefix.to_string()),
+            time: server_time(message),
+        })))
+    }
+}
+
+/// When the network says the message was sent, if it does.
+fn server_time(message: &ic::Message) -> Option<DateTime<Utc>> {
+    caps::tag(message, "time").and_then(|time| {
+        DateTime::parse_from_rfc3339(time)
+            .ok()
+            .map(|time| time.with_timezone(&Utc))
+    })
+}
+
+/// Someone other than us arriving, leaving, or changing name; `shared` is where they were,
+/// for a quit or rename.
+fn activity(us: &str, nick: &str, message: &ic::Message, shared: Vec<String>) -> Option<Activity> {
+    let reason = |reason: &Option<String>| reason.as_deref().unwrap_or_default().to_string();
+    let source = nick;
+    let (nick, doing) = match message.command {
+        ic::Command::JOIN(ref chan, _, _) => (
+            nick,
+            Doing::Joined {
+                channel: chan.to_string(),
+            },
+        ),
+        ic::Command::PART(ref chan, ref why) => (
+            nick,
+            Doing::Parted {
+                channel: chan.to_string(),
+                reason: reason(why),
+            },
+        ),
+        ic::Command::KICK(ref chan, ref victim, ref why) => (
+            victim.as_str(),
+            Doing::Kicked {
+                channel: chan.to_string(),
+                by: nick.to_string(),
+                reason: reason(why),
+            },
+        ),
+        ic::Command::QUIT(ref why) => (
+            nick,
+            Doing::Quit {
+                reason: reason(why),
+                channels: shared,
+            },
+        ),
+        ic::Command::NICK(ref to) => (
+            nick,
+            Doing::Renamed {
+                to: to.to_string(),
+                channels: shared,
+            },
+        ),
+        _ => return None,
+    };
+
+    if nick == us {
+        return None;
+    }
+
+    // with extended-join, joins say who people are even without account-tag
+    let account = match message.command {
+        ic::Command::JOIN(_, Some(ref account), _) if account != "*" => Some(account.as_str()),
+        // the tag is whoever did it, e.g. the kicker, not who it was done to
+        _ if nick != source => None,
+        _ => caps::tag(message, "account"),
+    };
+
+    Some(Activity {
+        nick: nick.to_string(),
+        account: account.map(str::to_string),
+        doing,
+        time: server_time(message),
+    })
+}
+
+impl Transport for Irc {
+    fn name(&self) -> &str {
+        &self.network.name
+    }
+
+    fn nick(&self) -> String {
+        match &self.connection {
+            Some(connection) => connection.client.current_nickname().to_string(),
+            None => self.network.server.nick.to_string(),
+        }
+    }
+
+    fn channel_settings(&self, channel: &str) -> ChannelSettings {
+        self.network.server.settings(channel)
+    }
+
+    fn outbox(&self) -> Arc<dyn Outbox> {
+        self.outbox.clone()
+    }
+
+    async fn next(&mut self) -> Option<Event> {
+        loop {
+            if self.outbox.quitting.load(Ordering::Relaxed) {
+                self.hang_up().await;
+                return None;
+            }
+
+            let connection = match &mut self.connection {
+                Some(connection) => connection,
+                None => {
+                    if let Err(e) = self.connect().await {
+                        warn!("{}: connection failed: {:?}", self.network.name, e);
+                        self.reconnect_later().await;
+                    }
+                    continue;
+                }
+            };
+
+            let message = match connection.stream.next().await {
+                Some(Ok(message)) => message,
+                Some(Err(e)) => {
+                    warn!("{}: connection failed: {:?}", self.network.name, e);
+                    self.reconnect_later().await;
+                    continue;
+                }
+                None => {
+                    warn!("{}: connection closed", self.network.name);
+                    self.reconnect_later().await;
+                    continue;
+                }
+            };
+
+            match self.process(&message) {
+                Ok(Some(event)) => return Some(event),
+                Ok(None) => (),
+                Err(e) => {
+                    warn!("{}: giving up on connection: {:?}", self.network.name, e);
+                    self.reconnect_later().await;
+                }
+            }
+        }
+    }
+}
+
+impl IrcOutbox {
+    /// A new connection (or none); forgets everything about the old one.
+    fn replace(&self, sender: Option<Sender>) {
+        *self.sender.lock().expect("poisoned") = sender;
+        self.caps.lock().expect("poisoned").clear();
+        self.colourless.lock().expect("poisoned").clear();
+        self.pending.lock().expect("poisoned").clear();
+    }
+
+    fn enable(&self, caps: Vec<String>) {
+        self.caps.lock().expect("poisoned").extend(caps);
+    }
+
+    fn has_cap(&self, cap: &str) -> bool {
+        self.caps.lock().expect("poisoned").contains(cap)
+    }
+
+    fn delivered(&self, label: &str) {
+        if let Some((target, sent)) = self.pending.lock().expect("poisoned").remove(label) {
+            debug!("delivered to {:?} in {:?}", target, sent.elapsed());
+        }
+    }
+
+    fn check_deliveries(&self, network: &str) {
+        self.pending
+            .lock()
+            .expect("poisoned")
+            .retain(|label, (target, sent)| {
+                let waiting = sent.elapsed() < DELIVERY_TIMEOUT;
+                if !waiting {
+                    warn!(
+                        "{}: no confirmation that message {} to {:?} was delivered",
+                        network, label, target
+                    );
+                }
+                waiting
+            });
+    }
+
+    fn set_hostmask(&self, hostmask: String) {
+        *self.hostmask.lock().expect("poisoned") = hostmask;
+    }
+
+    fn sender(&self) -> Result<Sender> {
+        match &*self.sender.lock().expect("poisoned") {
+            Some(sender) => Ok(sender.clone()),
+            None => bail!("not connected"),
+        }
+    }
+}
+
+impl Outbox for IrcOutbox {
+    fn send(&self, reply: Reply) -> Result<()> {
+        let target = reply.target;
+        let colourless = self
+            .colourless
+            .lock()
+            .expect("poisoned")
+            .contains(&target.to_lowercase());
+        let text = if colourless {
+            format::strip(reply.text)
+        } else {
+            reply.text.into()
+        };
+
+        let mut tags = Vec::new();
+        if let Some(id) = reply.in_reply_to
+            && self.has_cap("message-tags")
+        {
+            tags.push(Tag("+draft/reply".to_string(), Some(id.to_string())));
+        }
+        if self.has_cap("labeled-response") {
+            let label = format!("u{}", self.next_label.fetch_add(1, Ordering::Relaxed));
+            self.pending
+                .lock()
+                .expect("poisoned")
+                .insert(label.to_string(), (target.to_string(), Instant::now()));
+            tags.push(Tag("label".to_string(), Some(label)));
+        }
+
+        Ok(self.sender()?.send(ic::Message {
+            tags: if tags.is_empty() { None } else { Some(tags) },
+            prefix: None,
+            command: if reply.notice {
+                ic::Command::NOTICE(target.to_string(), text.to_string())
+            } else {
+                ic::Command::PRIVMSG(target.to_string(), text.to_string())
+            },
+        })?)
+    }
+
+    fn max_payload(&self, target: &str) -> usize {
+        split::payload_limit(&self.hostmask.lock().expect("poisoned"), target)
+    }
+
+    fn join(&self, channel: &str) -> Result<()> {
+        Ok(self.sender()?.send_join(channel)?)
+    }
+
+    fn part(&self, channel: &str, reason: &str) -> Result<()> {
+        Ok(self.sender()?.send(ic::Command::PART(
+            channel.to_string(),
+            Some(reason.to_string()),
+        ))?)
+    }
+
+    fn quit(&self, reason: &str) -> Result<()> {
+        self.quitting.store(true, Ordering::Relaxed);
+        Ok(self.sender()?.send_quit(reason)?)
+    }
+}
+
+/// The configured channels, with what's been joined and parted at runtime; only the changes
+/// are saved, so channels removed from the config are forgotten.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+struct Channels {
+    #[serde(skip)]
+    configured: BTreeSet<String>,
+    /// not configured, but joined since
+    #[serde(default)]
+    joined: BTreeSet<String>,
+    /// configured, but parted since
+    #[serde(default)]
+    parted: BTreeSet<String>,
+    /// kicked from, or the server won't let us in; only until we restart, or join again
+    #[serde(skip)]
+    away: BTreeSet<String>,
+}
+
+impl Channels {
+    /// These changes, applied to this config, dropping any which no longer change anything.
+    fn on_top_of(mut self, configured: &[String]) -> Channels {
+        self.configured = configured.iter().cloned().collect();
+        self.joined.retain(|chan| !self.configured.contains(chan));
+        self.parted.retain(|chan| self.configured.contains(chan));
+        self
+    }
+
+    fn current(&self) -> Vec<String> {
+        self.configured
+            .iter()
+            .filter(|chan| !self.parted.contains(*chan))
+            .chain(&self.joined)
+            .filter(|chan| !self.away.contains(*chan))
+            .cloned()
+            .collect()
+    }
+
+    /// These return true if the changes to save changed; only joining and parting do that.
+    fn join(&mut self, chan: &str) -> bool {
+        self.away.remove(chan);
+        match self.configured.contains(chan) {
+            true => self.parted.remove(chan),
+            false => self.joined.insert(chan.to_string()),
+        }
+    }
+
+    fn part(&mut self, chan: &str) -> bool {
+        match self.configured.contains(chan) {
+            true => self.parted.insert(chan.to_string()),
+            false => self.joined.remove(chan),
+        }
+    }
+
+    /// Kicked, or refused entry: not a decision to leave, so nothing's saved.
+    fn lost(&mut self, chan: &str) {
+        self.away.insert(chan.to_string());
+    }
+}
+
+/// Which channels we share with each nick; the client's own lists have already forgotten
+/// someone by the time we hear that they quit.
+#[derive(Default)]
+struct Members {
+    /// lower-cased nick -> lower-cased channels
+    nicks: HashMap<String, BTreeSet<String>>,
+}
+
+impl Members {
+    /// Follows who's where; for a quit or rename, the channels they were in, before it.
+    fn track(&mut self, us: &str, message: &ic::Message) -> Vec<String> {
+        let source = message.source_nickname().unwrap_or_default().to_lowercase();
+        let us = us.to_lowercase();
+
+        match message.command {
+            ic::Command::JOIN(ref chan, _, _) if source != us => {
+                self.join(&source, chan);
+            }
+            ic::Command::PART(ref chan, _) => self.part(&us, &source, chan),
+            ic::Command::KICK(ref chan, ref victim, _) => {
+                self.part(&us, &victim.to_lowercase(), chan)
+            }
+            ic::Command::QUIT(_) => {
+                return self.nicks.remove(&source).into_iter().flatten().collect();
+            }
+            ic::Command::NICK(ref to) => {
+                let channels = self.nicks.remove(&source).unwrap_or_default();
+                let shared = channels.iter().cloned().collect();
+                self.nicks.insert(to.to_lowercase(), channels);
+                return shared;
+            }
+            ic::Command::Response(ic::Response::RPL_NAMREPLY, ref args) if args.len() >= 4 => {
+                for name in args[3].split_whitespace() {
+                    let nick = name.trim_start_matches(['~', '&', '@', '%', '+']);
+                    // with userhost-in-names
+                    let nick = nick.split('!').next().unwrap_or_default();
+                    if !nick.is_empty() && nick.to_lowercase() != us {
+                        self.join(&nick.to_lowercase(), &args[2]);
+                    }
+                }
+            }
+            _ => (),
+        }
+        Vec::new()
+    }
+
+    fn join(&mut self, nick: &str, chan: &str) {
+        self.nicks
+            .entry(nick.to_string())
+            .or_default()
+            .insert(chan.to_lowercase());
+    }
+
+    /// `nick` left `chan`; if it's us, everyone did, as far as we can tell.
+    fn part(&mut self, us: &str, nick: &str, chan: &str) {
+        let chan = chan.to_lowercase();
+        let nicks: Vec<String> = match nick == us {
+            true => self.nicks.keys().cloned().collect(),
+            false => vec![nick.to_string()],
+        };
+        for nick in nicks {
+            if let Some(channels) = self.nicks.get_mut(&nick) {
+                channels.remove(&chan);
+                if channels.is_empty() {
+                    self.nicks.remove(&nick);
+                }
+            }
+        }
+    }
+}
+
+/// Keeps `channels` up to date; true if there are changes to save.
+fn track_channels(client: &ic::Client, message: &ic::Message, channels: &mut Channels) -> bool {
+    let us = client.current_nickname();
+    let from_us = message.source_nickname() == Some(us);
+
+    match message.command {
+        ic::Command::JOIN(ref chan, _, _) if from_us => channels.join(chan),
+        ic::Command::PART(ref chan, _) if from_us => channels.part(chan),
+        ic::Command::KICK(ref chan, ref victim, _) if victim == us => {
+            channels.lost(chan);
+            false
+        }
+        // there's no point trying these again on the next connection
+        ic::Command::Response(
+            ic::Response::ERR_BANNEDFROMCHAN
+            | ic::Response::ERR_INVITEONLYCHAN
+            | ic::Response::ERR_BADCHANNELKEY
+            | ic::Response::ERR_CHANNELISFULL,
+            ref args,
+        ) if args.len() >= 2 => {
+            warn!("can't join {}: {:?}", args[1], args.last());
+            channels.lost(&args[1]);
+            false
+        }
+        _ => false,
+    }
+}
+
+/// How long to wait before rejoining after this kick, if we're going to at all.
+fn rejoin_delay(
+    server: &config::Server,
+    kicks: &mut HashMap<String, Vec<Instant>>,
+    chan: &str,
+) -> Option<Duration> {
+    let recent = kicks.entry(chan.to_lowercase()).or_default();
+    recent.retain(|when| when.elapsed() < REJOIN_WINDOW);
+    if recent.len() >= server.rejoin_limit {
+        return None;
+    }
+    recent.push(Instant::now());
+    Some(Duration::from_secs(server.rejoin_delay))
+}
+
+/// `rejoin_limit` is per this long.
+const REJOIN_WINDOW: Duration = Duration::from_secs(60 * 60);
+
+fn load_channels(path: &Path) -> Result<Channels> {
+    match fs::read(path) {
+        Ok(bytes) => Ok(
+            serde_json::from_slice(&bytes).with_context(|| format_err!("parsing {:?}", path))?
+        ),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Channels::default()),
+        Err(e) => Err(e).with_context(|| format_err!("reading {:?}", path)),
+    }
+}
+
+/// Replaces the file in one go, so a crash can't leave half a list.
+fn save_channels(path: &Path, channels: &Channels) -> Result<()> {
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    fs::create_dir_all(dir).with_context(|| format_err!("creating {:?}", dir))?;
+    let mut temp = NamedTempFile::new_in(dir)?;
+    serde_json::to_writer_pretty(&mut temp, channels)?;
+    temp.persist(path)?;
+    Ok(())
+}
+
+fn file_safe(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// Notice which channels are +c (no colours), asking about each one we join.
+fn track_modes(client: &ic::Client, message: &ic::Message, outbox: &IrcOutbox) -> Result<()> {
+    let mut colourless = outbox.colourless.lock().expect("poisoned");
+    match message.command {
+        ic::Command::JOIN(ref chan, _, _)
+            if message.source_nickname() == Some(client.current_nickname()) =>
+        {
+            client.send(ic::Command::Raw("MODE".to_string(), vec![chan.to_string()]))?;
+        }
+        ic::Command::Response(ic::Response::RPL_CHANNELMODEIS, ref args) if args.len() >= 3 => {
+            let chan = args[1].to_lowercase();
+            if args[2].contains('c') {
+                colourless.insert(chan);
+            } else {
+                colourless.remove(&chan);
+            }
+        }
+        ic::Command::ChannelMODE(ref chan, ref modes) => {
+            for mode in modes {
+                match mode {
+                    Mode::Plus(ChannelMode::Unknown('c'), _) => {
+                        colourless.insert(chan.to_lowercase());
+                    }
+                    Mode::Minus(ChannelMode::Unknown('c'), _) => {
+                        colourless.remove(&chan.to_lowercase());
+                    }
+                    _ => (),
+                }
+            }
+        }
+        _ => (),
+    }
+    Ok(())
+}
+
+fn is_channel(target: &str) -> bool {
+    target.starts_with(['#', '&', '+', '!'])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use irc::client::prelude as ic;
+
+    use super::Channels;
+    use super::Members;
+    use super::activity;
+    use super::file_safe;
+    use super::load_channels;
+    use super::rejoin_delay;
+    use super::save_channels;
+    use crate::config::Config;
+
+    #[test]
+    fn rejoining() {
+        let config: Config = toml::from_str(include_str!("../../bot.toml.example")).unwrap();
+        let mut server = config.server.unwrap();
+        let mut kicks = HashMap::new();
+        assert_eq!(None, rejoin_delay(&server, &mut kicks, "#unsnap"));
+
+        server.rejoin_limit = 2;
+        server.rejoin_delay = 5;
+        let delay = Some(Duration::from_secs(5));
+        assert_eq!(delay, rejoin_delay(&server, &mut kicks, "#unsnap"));
+        assert_eq!(delay, rejoin_delay(&server, &mut kicks, "#UNSNAP"));
+        assert_eq!(None, rejoin_delay(&server, &mut kicks, "#unsnap"));
+        assert_eq!(delay, rejoin_delay(&server, &mut kicks, "#other"));
+    }
+
+    #[test]
+    fn activities() {
+        let seen = |line: &str| {
+            let message: ic::Message = line.parse().unwrap();
+            let source = message.source_nickname().unwrap().to_string();
+            activity("us", &source, &message, Vec::new()).unwrap()
+        };
+
+        let kicked = seen("@account=opaccount :op!o@host KICK #chan bob :bye");
+        assert_eq!("bob", kicked.nick);
+        assert_eq!(None, kicked.account);
+
+        let joined = seen(":bob!b@host JOIN #chan bobby :Bob Smith");
+        assert_eq!(Some("bobby".to_string()), joined.account);
+        let joined = seen(":bob!b@host JOIN #chan * :Bob Smith");
+        assert_eq!(None, joined.account);
+        let joined = seen("@account=bobby :bob!b@host JOIN #chan");
+        assert_eq!(Some("bobby".to_string()), joined.account);
+
+        let quit = seen("@account=bobby :bob!b@host QUIT :bye");
+        assert_eq!(Some("bobby".to_string()), quit.account);
+    }
+
+    #[test]
+    fn membership() {
+        let mut members = Members::default();
+        let mut track = |line: &str| members.track("us", &line.parse::<ic::Message>().unwrap());
+        track(":server 353 us = #Chan :@us bob +Alice");
+        track(":server 353 us = #quiet :bob");
+        track(":carol!c@host JOIN #chan");
+        track(":bob!b@host PART #quiet");
+        assert_eq!(vec!["#chan"], track(":bob!b@host NICK robert"));
+        assert_eq!(vec!["#chan"], track(":Robert!b@host QUIT :bye"));
+        assert!(track(":robert!b@host QUIT :bye").is_empty());
+
+        // we left, so we've no idea where anyone there is now
+        track(":us!u@host PART #chan");
+        assert!(track(":alice!a@host QUIT :bye").is_empty());
+        assert!(track(":carol!c@host NICK caz").is_empty());
+    }
+
+    #[test]
+    fn persistence() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir
+            .path()
+            .join("sub")
+            .join(format!("channels-{}.json", file_safe("irc/x")));
+        assert!(path.ends_with("channels-irc_x.json"));
+
+        assert_eq!(Channels::default(), load_channels(&path).unwrap());
+        let configured = |names: &[&str]| names.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        let mut channels = Channels::default().on_top_of(&configured(&["#a", "#b"]));
+        assert!(channels.join("#c"));
+        assert!(channels.part("#a"));
+        assert!(channels.join("#e"));
+        // kicked, or banned, isn't leaving on purpose
+        channels.lost("#b");
+        channels.lost("#e");
+        assert_eq!(vec!["#c"], channels.current());
+        save_channels(&path, &channels).unwrap();
+
+        // #a was removed from the config, so its part no longer means anything
+        let restored = load_channels(&path)
+            .unwrap()
+            .on_top_of(&configured(&["#b", "#d"]));
+        assert_eq!(vec!["#b", "#d", "#c", "#e"], restored.current());
+        assert!(restored.parted.is_empty());
+    }
+}
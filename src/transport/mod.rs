@@ -0,0 +1,121 @@
+pub mod console;
+pub mod irc;
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use chrono::DateTime;
+use chrono::Utc;
+
+use crate::config::ChannelSettings;
+
+/// Something which happened on a chat network, which the bot might care about.
+#[derive(Clone, Debug)]
+pub enum Event {
+    Message(Message),
+    Activity(Activity),
+}
+
+/// Someone arriving, leaving or changing name, where we can see them.
+#[derive(Clone, Debug)]
+pub struct Activity {
+    pub nick: String,
+    /// their services account, if the network tells us
+    pub account: Option<String>,
+    pub doing: Doing,
+    /// when the network says it happened, if it does
+    pub time: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug)]
+pub enum Doing {
+    Joined {
+        channel: String,
+    },
+    Parted {
+        channel: String,
+        reason: String,
+    },
+    Kicked {
+        channel: String,
+        by: String,
+        reason: String,
+    },
+    /// from the network, so from every channel
+    Quit {
+        reason: String,
+        /// the channels we were both in
+        channels: Vec<String>,
+    },
+    Renamed {
+        to: String,
+        /// the channels we're both in
+        channels: Vec<String>,
+    },
+}
+
+/// Text from someone, to a channel or to us.
+#[derive(Clone, Debug)]
+pub struct Message {
+    /// who said it
+    pub source: String,
+    /// where they said it: a channel, or our own name
+    pub target: String,
+    pub text: String,
+    /// sent directly to us, rather than to a channel
+    pub private: bool,
+    /// an emote (`/me`), rather than ordinary text
+    pub action: bool,
+    /// the network's id for the message, which replies can refer to
+    pub id: Option<String>,
+    /// the sender's services account, if the network tells us
+    pub account: Option<String>,
+    /// `nick!user@host`, where the network has such a thing
+    pub hostmask: Option<String>,
+    /// when the network says it was sent, if it does
+    pub time: Option<DateTime<Utc>>,
+}
+
+/// Something for the bot to say.
+#[derive(Clone, Debug)]
+pub struct Reply<'a> {
+    /// a channel, or a nick
+    pub target: &'a str,
+    pub text: &'a str,
+    /// as a NOTICE, rather than a normal message
+    pub notice: bool,
+    /// threaded as a reply to the message with this id, where the network supports that
+    pub in_reply_to: Option<&'a str>,
+}
+
+/// The sending half of a transport, shareable with the tasks which produce replies.
+pub trait Outbox: Send + Sync {
+    /// Formatting is removed where the target can't show it.
+    fn send(&self, reply: Reply) -> Result<()>;
+
+    /// The longest text (in bytes) which `send` can deliver to the target intact.
+    fn max_payload(&self, target: &str) -> usize;
+
+    fn join(&self, channel: &str) -> Result<()>;
+
+    fn part(&self, channel: &str, reason: &str) -> Result<()>;
+
+    /// Disconnects for good; the transport runs out of events soon after.
+    fn quit(&self, reason: &str) -> Result<()>;
+}
+
+/// A connection to somewhere people chat; the bot core is driven through this.
+pub trait Transport {
+    /// For logs.
+    fn name(&self) -> &str;
+
+    /// What we're called, so people can address us.
+    fn nick(&self) -> String;
+
+    fn channel_settings(&self, channel: &str) -> ChannelSettings;
+
+    fn outbox(&self) -> Arc<dyn Outbox>;
+
+    /// The next event, (re)connecting as necessary; `None` when there will never be more.
+    async fn next(&mut self) -> Option<Event>;
+}
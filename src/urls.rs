@@ -0,0 +1,290 @@
+//! `!url`: the links posted in each channel, and their titles, kept for searching.
+
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Result;
+use chrono::DateTime;
+use chrono::TimeDelta;
+use chrono::Utc;
+use rusqlite::params;
+
+use crate::config;
+use crate::db::Db;
+use crate::format;
+use crate::split;
+use crate::titles::Title;
+
+pub const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS urls (
+        id INTEGER PRIMARY KEY,
+        network TEXT NOT NULL,
+        -- lower-cased
+        channel TEXT NOT NULL,
+        nick TEXT NOT NULL,
+        -- unix seconds
+        at INTEGER NOT NULL,
+        -- as posted
+        url TEXT NOT NULL,
+        -- after redirects
+        final_url TEXT NOT NULL,
+        title TEXT NOT NULL,
+        -- what titled it, e.g. youtube or html
+        provider TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS urls_channel ON urls (network, channel, at);
+    CREATE INDEX IF NOT EXISTS urls_at ON urls (at);
+    CREATE VIRTUAL TABLE IF NOT EXISTS urls_search
+        USING fts5(title, content = 'urls', content_rowid = 'id');
+    CREATE TRIGGER IF NOT EXISTS urls_searchable AFTER INSERT ON urls BEGIN
+        INSERT INTO urls_search (rowid, title) VALUES (new.id, new.title);
+    END;
+    CREATE TRIGGER IF NOT EXISTS urls_unsearchable AFTER DELETE ON urls BEGIN
+        INSERT INTO urls_search (urls_search, rowid, title) VALUES ('delete', old.id, old.title);
+    END;
+";
+
+/// Titles are shown only up to this many bytes, so a few links fit.
+const MAX_SHOWN_TITLE: usize = 100;
+
+/// How often old links are deleted.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+pub struct Urls {
+    db: Arc<Db>,
+    config: config::Urls,
+    purged: Mutex<Option<Instant>>,
+}
+
+impl Urls {
+    pub fn new(db: Arc<Db>, config: &config::Urls) -> Urls {
+        Urls {
+            db,
+            config: config.clone(),
+            purged: Mutex::default(),
+        }
+    }
+
+    /// Keeps a link someone posted in a channel, and the title we found for it.
+    pub fn record(
+        &self,
+        network: &str,
+        channel: &str,
+        nick: &str,
+        title: &Title,
+        at: DateTime<Utc>,
+    ) -> Result<()> {
+        if !self.config.enabled {
+            return Ok(());
+        }
+
+        self.purge()?;
+        self.db.with(|db| {
+            db.execute(
+                "INSERT INTO urls (network, channel, nick, at, url, final_url, title, provider)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                params![
+                    network,
+                    channel.to_lowercase(),
+                    nick,
+                    at.timestamp(),
+                    title.url,
+                    title.found.url,
+                    title.title,
+                    title.found.provider
+                ],
+            )
+        })?;
+        Ok(())
+    }
+
+    /// Forgets links older than the retention, at most every `PURGE_INTERVAL`.
+    fn purge(&self) -> Result<()> {
+        let mut purged = self.purged.lock().expect("poisoned");
+        if purged.is_some_and(|when| when.elapsed() < PURGE_INTERVAL) {
+            return Ok(());
+        }
+        *purged = Some(Instant::now());
+        let deleted = self.db.with(|db| {
+            db.execute(
+                "DELETE FROM urls WHERE at < ?1",
+                params![self.cutoff(Utc::now()).timestamp()],
+            )
+        })?;
+        if deleted > 0 {
+            info!("forgot {} old links", deleted);
+        }
+        Ok(())
+    }
+
+    fn cutoff(&self, now: DateTime<Utc>) -> DateTime<Utc> {
+        now - TimeDelta::days(self.config.retention_days as i64)
+    }
+
+    /// `!url search <terms>`, or `!url last [nick]`, for the channel it's asked in.
+    pub fn answer(
+        &self,
+        network: &str,
+        channel: Option<&str>,
+        args: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<String>> {
+        if !self.config.enabled {
+            return Ok(vec!["I'm not keeping links.".to_string()]);
+        }
+        let Some(channel) = channel else {
+            return Ok(vec![
+                "Ask in the channel; links are kept for each.".to_string(),
+            ]);
+        };
+
+        let (what, rest) = args.trim().split_once(' ').unwrap_or((args.trim(), ""));
+        let (query, nick) = match (what, rest.trim()) {
+            ("search", "") => return Ok(vec!["Search for what?".to_string()]),
+            ("search", terms) => (Some(search_terms(terms)), None),
+            ("last", "") => (None, None),
+            ("last", nick) => (None, Some(nick.to_lowercase())),
+            _ => return Ok(vec!["Try search <words>, or last [nick].".to_string()]),
+        };
+
+        let found: Vec<(String, String, String, i64)> = self.db.with(|db| {
+            let mut statement = db.prepare_cached(
+                "SELECT nick, url, title, at FROM urls
+                 WHERE network = ?1 AND channel = ?2 AND at >= ?3
+                   AND (?4 IS NULL OR id IN (SELECT rowid FROM urls_search WHERE urls_search MATCH ?4))
+                   AND (?5 IS NULL OR lower(nick) = ?5)
+                 ORDER BY at DESC, id DESC
+                 LIMIT ?6",
+            )?;
+            statement
+                .query_map(
+                    params![
+                        network,
+                        channel.to_lowercase(),
+                        self.cutoff(now).timestamp(),
+                        query,
+                        nick,
+                        self.config.max_results
+                    ],
+                    |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+                )?
+                .collect()
+        })?;
+
+        if found.is_empty() {
+            return Ok(vec!["I haven't seen any links like that.".to_string()]);
+        }
+        Ok(found
+            .into_iter()
+            .map(|(nick, url, title, at)| {
+                let at = DateTime::from_timestamp(at, 0).unwrap_or(now);
+                format!(
+                    "{} - {} ({}, {} ago)",
+                    split::elide(&title, MAX_SHOWN_TITLE),
+                    url,
+                    nick,
+                    format::ago(now - at)
+                )
+            })
+            .collect())
+    }
+}
+
+/// Each word as a quoted fts5 string, so all have to match, and punctuation isn't syntax.
+fn search_terms(terms: &str) -> String {
+    terms
+        .split_whitespace()
+        .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+
+    use super::Urls;
+    use crate::config;
+    use crate::db;
+    use crate::titles::Found;
+    use crate::titles::Title;
+
+    #[test]
+    fn searching() {
+        let urls = Urls::new(db::scratch(), &config::Urls::default());
+        let then = db::some_time();
+        let title = |url: &str, title: &str| Title {
+            host: "example.com".to_string(),
+            title: title.to_string(),
+            cleaned: None,
+            url: url.to_string(),
+            found: Found {
+                title: title.to_string(),
+                provider: "html",
+                url: url.to_string(),
+            },
+        };
+        urls.record(
+            "net",
+            "#Chan",
+            "faux",
+            &title("https://a/", "Rust 2024 is out"),
+            then,
+        )
+        .unwrap();
+        urls.record(
+            "net",
+            "#chan",
+            "bob",
+            &title("https://b/", "Cats: \"a\" history"),
+            then,
+        )
+        .unwrap();
+        urls.record(
+            "net",
+            "#other",
+            "bob",
+            &title("https://c/", "Rust elsewhere"),
+            then,
+        )
+        .unwrap();
+
+        let now = then + TimeDelta::hours(2);
+        let url = |args| urls.answer("net", Some("#chan"), args, now).unwrap();
+        assert_eq!(
+            vec!["Rust 2024 is out - https://a/ (faux, 2 hours ago)"],
+            url("search rust")
+        );
+        assert_eq!(
+            vec!["Cats: \"a\" history - https://b/ (bob, 2 hours ago)"],
+            url("search \"history\" (cats")
+        );
+        // only what was posted in the channel it's asked in
+        assert_eq!(2, url("last").len());
+        assert!(
+            urls.answer("net", None, "last", now).unwrap()[0].starts_with("Ask in the channel")
+        );
+        assert_eq!(
+            vec!["Rust 2024 is out - https://a/ (faux, 2 hours ago)"],
+            url("last Faux")
+        );
+        assert_eq!(
+            vec!["I haven't seen any links like that."],
+            url("search dogs")
+        );
+
+        let retention = TimeDelta::days(config::Urls::default().retention_days as i64);
+        assert_eq!(
+            vec!["I haven't seen any links like that."],
+            urls.answer(
+                "net",
+                Some("#chan"),
+                "last",
+                then + retention + TimeDelta::hours(1)
+            )
+            .unwrap()
+        );
+    }
+}
@@ -1,4 +1,6 @@
+use std::collections::BTreeMap;
 use std::collections::HashMap;
+use std::collections::HashSet;
 use std::ops::Deref;
 use std::sync::{Arc, Mutex};
 use std::time;
@@ -11,29 +13,90 @@ use maplit::hashmap;
 use reqwest::Client;
 use reqwest::Response;
 use serde_json::Value;
+use tokio::sync::watch;
 
+use crate::commands::Registry;
 use crate::config::Config;
+use crate::danger::Qalc;
+use crate::danger::Tools;
+use crate::db::Db;
+use crate::remind::Reminders;
+use crate::seen::Seen;
+use crate::tell::Tells;
+use crate::titles::Found;
+use crate::titles::clean::Cleaner;
+use crate::titles::rules::Rules;
+use crate::titles::scripts::Scripts;
+use crate::urls::Urls;
 
 pub struct Context {
     pub config: Config,
     pub state: State,
+    pub commands: Registry,
+    pub qalc: Qalc,
+    pub tools: Tools,
+    pub seen: Seen,
+    pub tells: Tells,
+    pub reminders: Reminders,
+    pub urls: Urls,
+    titling: Mutex<Arc<Titling>>,
+}
+
+/// The parts of the configuration which can be reloaded while running.
+pub struct Titling {
+    pub rules: Rules,
+    pub scripts: Scripts,
+    pub cleaner: Cleaner,
+}
+
+impl Titling {
+    fn new(http: &Client, config: &Config) -> Result<Titling> {
+        Ok(Titling {
+            rules: Rules::new(&config.rules)?,
+            scripts: Scripts::load(http, config.scripts.as_deref())?,
+            cleaner: Cleaner::new(&config.clean),
+        })
+    }
 }
 
 impl Context {
-    pub fn new(config: Config) -> (Client, Context) {
+    pub fn new(config: Config) -> Result<(Client, Context)> {
         let ua = chrome_ua();
         info!("UA: {}", ua);
         let client = reqwest::ClientBuilder::new()
             .user_agent(ua)
             .build()
             .expect("infallible");
-        (
+        let titling = Titling::new(&client, &config)?;
+        let db = Arc::new(Db::open(config.data_dir.as_deref())?);
+        Ok((
             client,
             Context {
+                commands: Registry::new(&config)?,
+                qalc: Qalc::new(&config.qalc),
+                tools: Tools::new(&config.tools),
+                seen: Seen::new(Arc::clone(&db), &config.seen),
+                tells: Tells::new(Arc::clone(&db), &config.tell),
+                reminders: Reminders::new(Arc::clone(&db), &config.remind, &config.time),
+                urls: Urls::new(Arc::clone(&db), &config.urls),
                 config,
                 state: State::default(),
+                titling: Mutex::new(Arc::new(titling)),
             },
-        )
+        ))
+    }
+
+    /// The current rules, scripts and cleaner; a reload doesn't affect ones already handed out.
+    pub fn titling(&self) -> Arc<Titling> {
+        Arc::clone(&self.titling.lock().expect("poisoned"))
+    }
+
+    /// Re-reads the config file, and replaces the rules, scripts and cleaner from it.
+    pub fn reload(&self, http: &Client) -> Result<()> {
+        let config = Config::load()?;
+        let titling = Titling::new(http, &config)?;
+        *self.titling.lock().expect("poisoned") = Arc::new(titling);
+        Ok(())
     }
 }
 
@@ -61,14 +124,14 @@ pub fn errors(resp: Response) -> Result<Response> {
 
 pub async fn imgur_get(client: &Client, config: &Config, sub: &str) -> Result<Value> {
     let resp = client
-        .get(&format!("https://api.imgur.com/3/{}", sub))
+        .get(format!("https://api.imgur.com/3/{}", sub))
         .header(
             "Authorization",
             &format!("Client-ID {}", &config.keys.imgur_client_id),
         )
         .send()
         .await?;
-    Ok(resp.json().await.context("bad json from imgur")?)
+    resp.json().await.context("bad json from imgur")
 }
 
 pub async fn twitter_get(client: &Client, context: Arc<Context>, sub: &str) -> Result<Value> {
@@ -93,12 +156,12 @@ pub async fn twitter_get(client: &Client, context: Arc<Context>, sub: &str) -> R
         .expect("populated above");
     let resp = errors(
         client
-            .get(&format!("https://api.twitter.com/{}", sub))
+            .get(format!("https://api.twitter.com/{}", sub))
             .header("Authorization", &token)
             .send()
             .await?,
     )?;
-    Ok(resp.json().await.context("bad json from twitter")?)
+    resp.json().await.context("bad json from twitter")
 }
 
 pub async fn spotify_get(client: &Client, context: Arc<Context>, sub: &str) -> Result<Value> {
@@ -117,7 +180,7 @@ pub async fn spotify_get(client: &Client, context: Arc<Context>, sub: &str) -> R
             .with_context(|| format_err!("network fetching {:?}", url))?;
 
         if resp.status().is_success() {
-            return Ok(resp.json().await.context("bad json from spotify")?);
+            return resp.json().await.context("bad json from spotify");
         }
 
         if resp.status().as_u16() == 401 {
@@ -144,7 +207,7 @@ async fn maybe_refresh(client: &Client, context: &Arc<Context>) -> Result<String
 
     Ok(match token {
         None => {
-            let new_value = State::fetch_spotify_token(client, &context).await?;
+            let new_value = State::fetch_spotify_token(client, context).await?;
             context
                 .state
                 .spotify_token
@@ -182,16 +245,39 @@ pub async fn youtube_get(
     )
     .unwrap();
 
-    Ok(errors(client.get(url.as_str()).send().await?)?
+    errors(client.get(url.as_str()).send().await?)?
         .json()
         .await
-        .context("bad json from youtube")?)
+        .context("bad json from youtube")
 }
 
+/// How long a url's title is reused for, instead of fetching it again.
+const TITLE_TTL: time::Duration = time::Duration::from_secs(15 * 60);
+const TITLE_CACHE_SIZE: usize = 1000;
+
+/// Fetches allowed to one host in a minute, across every network and channel.
+const FETCHES_PER_MINUTE: usize = 20;
+
+/// Shared by every network the bot is on.
 #[derive(Default)]
 pub struct State {
     twitter_token: Mutex<Option<String>>,
     spotify_token: Mutex<Option<String>>,
+    titles: Mutex<HashMap<String, (time::Instant, Option<Found>)>>,
+    fetches: Mutex<HashMap<String, Vec<time::Instant>>>,
+    providers: Mutex<BTreeMap<&'static str, Provider>>,
+    /// (network, lower-cased channel) pairs where the bot stays quiet
+    muted: Mutex<HashSet<(String, String)>>,
+    /// the quit message, once someone has asked the bot to quit
+    shutdown: watch::Sender<Option<String>>,
+}
+
+/// How a title source has been doing.
+#[derive(Default)]
+struct Provider {
+    ok: u64,
+    failed: u64,
+    last_error: Option<(time::Instant, String)>,
 }
 
 async fn oauth_token(client: &Client, url: &str, key: &str, secret: &str) -> Result<String> {
@@ -216,6 +302,107 @@ async fn oauth_token(client: &Client, url: &str, key: &str, secret: &str) -> Res
 }
 
 impl State {
+    /// `Some` if we've looked at this url recently, even if it had no title.
+    pub fn cached_title(&self, url: &str) -> Option<Option<Found>> {
+        self.titles
+            .lock()
+            .expect("poisoned")
+            .get(url)
+            .filter(|(when, _)| when.elapsed() < TITLE_TTL)
+            .map(|(_, title)| title.clone())
+    }
+
+    pub fn cache_title(&self, url: &str, title: Option<Found>) {
+        let mut titles = self.titles.lock().expect("poisoned");
+        if titles.len() >= TITLE_CACHE_SIZE {
+            titles.retain(|_, (when, _)| when.elapsed() < TITLE_TTL);
+        }
+        if titles.len() >= TITLE_CACHE_SIZE {
+            titles.clear();
+        }
+        titles.insert(url.to_string(), (time::Instant::now(), title));
+    }
+
+    /// Forgets titles, rate limits and api tokens.
+    pub fn clear_caches(&self) {
+        self.titles.lock().expect("poisoned").clear();
+        self.fetches.lock().expect("poisoned").clear();
+        self.twitter_token.lock().expect("poisoned").take();
+        self.spotify_token.lock().expect("poisoned").take();
+    }
+
+    /// Counts the outcome of asking a title source about a link.
+    pub fn record<T>(&self, provider: &'static str, result: &Result<T>) {
+        let mut providers = self.providers.lock().expect("poisoned");
+        let provider = providers.entry(provider).or_default();
+        match result {
+            Ok(_) => provider.ok += 1,
+            Err(e) => {
+                provider.failed += 1;
+                provider.last_error = Some((time::Instant::now(), format!("{:#}", e)));
+            }
+        }
+    }
+
+    /// A line per title source which has been used.
+    pub fn provider_status(&self) -> Vec<String> {
+        self.providers
+            .lock()
+            .expect("poisoned")
+            .iter()
+            .map(|(name, provider)| {
+                let mut line = format!("{}: {} ok, {} failed", name, provider.ok, provider.failed);
+                if let Some((when, error)) = &provider.last_error {
+                    line.push_str(&format!("; {}s ago: {}", when.elapsed().as_secs(), error));
+                }
+                line
+            })
+            .collect()
+    }
+
+    pub fn set_muted(&self, network: &str, channel: &str, muted: bool) {
+        let key = (network.to_string(), channel.to_lowercase());
+        let mut all = self.muted.lock().expect("poisoned");
+        if muted {
+            all.insert(key);
+        } else {
+            all.remove(&key);
+        }
+    }
+
+    pub fn is_muted(&self, network: &str, channel: &str) -> bool {
+        self.muted
+            .lock()
+            .expect("poisoned")
+            .contains(&(network.to_string(), channel.to_lowercase()))
+    }
+
+    /// Asks every network to disconnect.
+    pub fn shut_down(&self, reason: &str) {
+        self.shutdown.send_replace(Some(reason.to_string()));
+    }
+
+    pub fn shutdown(&self) -> watch::Receiver<Option<String>> {
+        self.shutdown.subscribe()
+    }
+
+    /// Records a fetch from the host, unless it's had too many recently.
+    pub fn allow_fetch(&self, host: &str) -> bool {
+        let minute = time::Duration::from_secs(60);
+        let mut fetches = self.fetches.lock().expect("poisoned");
+        fetches.retain(|_, recent| {
+            recent.retain(|when| when.elapsed() < minute);
+            !recent.is_empty()
+        });
+
+        let recent = fetches.entry(host.to_string()).or_default();
+        if recent.len() >= FETCHES_PER_MINUTE {
+            return false;
+        }
+        recent.push(time::Instant::now());
+        true
+    }
+
     async fn update_twitter_token(&self, client: &Client, config: &Config) -> Result<()> {
         let new_value = oauth_token(
             client,
@@ -295,3 +482,60 @@ pub async fn read_many(inner: &mut Response, mut buf: &mut [u8]) -> Result<usize
 
     Ok(total)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::FETCHES_PER_MINUTE;
+    use super::State;
+    use crate::titles::Found;
+
+    #[test]
+    fn title_cache() {
+        let state = State::default();
+        assert_eq!(None, state.cached_title("https://example.com/"));
+        state.cache_title("https://example.com/", None);
+        assert_eq!(Some(None), state.cached_title("https://example.com/"));
+        let found = Found {
+            title: "ponies".to_string(),
+            provider: "html",
+            url: "https://example.com/".to_string(),
+        };
+        state.cache_title("https://example.com/", Some(found.clone()));
+        assert_eq!(
+            Some(Some(found)),
+            state.cached_title("https://example.com/")
+        );
+    }
+
+    #[test]
+    fn providers_and_mutes() {
+        let state = State::default();
+        state.record("imgur", &Ok(()));
+        state.record::<()>("imgur", &Err(anyhow::anyhow!("nope")));
+        state.record("html", &Ok(()));
+        let status = state.provider_status();
+        assert_eq!("html: 1 ok, 0 failed", status[0]);
+        assert!(
+            status[1].starts_with("imgur: 1 ok, 1 failed; "),
+            "{}",
+            status[1]
+        );
+        assert!(status[1].ends_with(": nope"), "{}", status[1]);
+
+        state.set_muted("libera", "#Unsnap", true);
+        assert!(state.is_muted("libera", "#unsnap"));
+        assert!(!state.is_muted("oftc", "#unsnap"));
+        state.set_muted("libera", "#UNSNAP", false);
+        assert!(!state.is_muted("libera", "#unsnap"));
+    }
+
+    #[test]
+    fn fetch_limit() {
+        let state = State::default();
+        for _ in 0..FETCHES_PER_MINUTE {
+            assert!(state.allow_fetch("example.com"));
+        }
+        assert!(!state.allow_fetch("example.com"));
+        assert!(state.allow_fetch("example.org"));
+    }
+}